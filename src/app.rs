@@ -1,5 +1,17 @@
+use std::{fs, ops::RangeInclusive, path::PathBuf};
+
 use nanorand::{Rng, WyRand};
 
+use crate::{
+    export::{colormap_to_rgb8, rgb8_to_colormap, ImageFormat, ScreenshotMode},
+    graphics::{Filter, Renderer, FULLSCREEN_MIN_CELLS},
+    init::{self, ColorDraw, Image, ImagePalette, Pattern},
+    record::RecordFormat,
+    rule::{self, Boundary, Neighborhood, Rule},
+    simulation::{max_cell_number, max_neighborhood_size},
+    snapshot::Snapshot,
+};
+
 #[derive(Debug)]
 pub struct AppState {
     /// screen width (in px)
@@ -7,8 +19,8 @@ pub struct AppState {
     /// screen height (in px)
     pub height: u32,
 
-    /// length of the square (in px)
-    pub cell_dimension: u32,
+    /// length of the square (in px), fractional when the grid is scaled to fit the window
+    pub cell_dimension: f32,
     /// the cells, containing their colors
     // pub cells: Vec<u32>,
 
@@ -22,27 +34,160 @@ pub struct AppState {
 
     pub color_number: u32,
     pub colormap: Vec<f32>,
+
+    /// a cell is taken over when its number of enemies is within this range
+    pub threshold: RangeInclusive<u32>,
+    /// offsets (x, y) from a cell to each of its neighbors
+    pub neighborhood: Vec<(i32, i32)>,
+    /// what the neighbors outside of the grid are
+    pub boundary: Boundary,
+
+    /// seed of every random thing (colormap, initial cells), the same seed gives the same run
+    pub seed: u64,
+
+    /// generations computed between two frames at startup
+    pub steps_per_frame: u32,
+    /// run as fast as possible, no frame rate limit nor vsync
+    pub fast: bool,
+    /// how frames are presented, the default one depending on 'fast' if none
+    pub present_mode: Option<wgpu::PresentMode>,
+    /// targeted frame rate, unlimited if none
+    pub fps: Option<f32>,
+    /// how the cells are drawn
+    pub renderer: Renderer,
+    /// how the fullscreen renderer colors the pixels
+    pub filter: Filter,
+    /// when the window is resized, start over with a grid filling it instead of scaling the current one
+    pub regenerate_on_resize: bool,
+
+    /// generation at which a screenshot is taken without pressing any key
+    pub screenshot_at: Option<usize>,
+    pub screenshot_mode: ScreenshotMode,
+    /// screenshots are written next to it, numbered with their generation
+    pub screenshot_path: PathBuf,
+
+    /// where the recording is written, numbered with its first generation when not recording from the start
+    pub record_path: PathBuf,
+    /// record from the first generation, without pressing any key
+    pub record_at_start: bool,
+    /// number of frames of a recording
+    pub record_frames: u32,
+    /// generations between two frames of a recording, or of a dump
+    pub record_every: u32,
+
+    /// directory where every frame is written as a numbered image
+    pub dump_frames: Option<PathBuf>,
+    pub dump_format: ImageFormat,
+    /// write every frame as raw RGBA pixels on stdout
+    pub pipe_raw: bool,
+
+    /// cells of the first generation, following 'init' if none
+    pub initial_cells: Option<Vec<u32>>,
+    /// pattern of the first generation, again when the grid is regenerated
+    pub init: Pattern,
+    /// how the colors of its random cells are drawn
+    pub color_draw: ColorDraw,
+    /// image the first generation is sampled from, again when the grid is regenerated
+    pub init_image: Option<Image>,
+    /// number of the first generation, not 0 when resuming a snapshot
+    pub initial_generation: usize,
+    /// generation at which a snapshot is saved without pressing any key
+    pub save_at: Option<usize>,
+    /// snapshots are written next to it, numbered with their generation
+    pub save_path: PathBuf,
+
+    /// where the number of cells of each color at every generation is written, not counted if none
+    pub population_path: Option<PathBuf>,
 }
 
 impl AppState {
     pub fn new(
         AppArgs {
-            window_size: w,
+            width: w,
+            height: h,
             cell_number: wanted_cell_number,
+            grid,
             color_number,
+            threshold,
+            neighborhood,
+            boundary,
+            seed,
+            steps_per_frame,
+            fast,
+            present_mode,
+            fps,
+            renderer,
+            filter,
+            regenerate_on_resize,
+            screenshot_at,
+            screenshot_mode,
+            screenshot_path,
+            record_path,
+            record_at_start,
+            record_frames,
+            record_every,
+            dump_frames,
+            dump_format,
+            pipe_raw,
+            snapshot,
+            save_at,
+            save_path,
+            init,
+            color_draw,
+            init_image,
+            init_palette,
+            population_path,
+            ..
         }: AppArgs,
     ) -> Self {
-        let cell_area = w * w / wanted_cell_number;
-        let cell_dimension = (cell_area as f64).sqrt().ceil() as u32;
-        let (cwidth, cheihgt) = (w / cell_dimension, w / cell_dimension);
-
+        let (cell_dimension, cwidth, cheihgt) = match grid {
+            // exact grid: scaled to fit in the window, letterboxed in the other direction
+            Some((cwidth, cheihgt)) => {
+                let cell_dimension = (w as f32 / cwidth as f32).min(h as f32 / cheihgt as f32);
+                (cell_dimension, cwidth, cheihgt)
+            }
+            // approximate grid: the smallest square cells of which at most 'wanted_cell_number' fit,
+            // unless they'd be bigger than the window (very narrow ones can then have a few more)
+            None => {
+                let cell_count = |dimension: u32| (w / dimension) as u64 * (h / dimension) as u64;
+                let max_dimension = w.min(h);
+                let cell_dimension = (1..max_dimension)
+                    .find(|&dimension| cell_count(dimension) <= wanted_cell_number as u64)
+                    .unwrap_or(max_dimension);
+                (
+                    cell_dimension as f32,
+                    w / cell_dimension,
+                    h / cell_dimension,
+                )
+            }
+        };
         let real_cell_number = cwidth * cheihgt;
-        assert!(real_cell_number <= wanted_cell_number);
 
-        let mut rng = WyRand::new();
+        let seed = seed.unwrap_or_else(|| WyRand::new().generate::<u64>());
+        let mut rng = WyRand::new_seed(seed);
+        let mut colormap = match &snapshot {
+            Some(snapshot) => snapshot.colormap.clone(),
+            None => (0..color_number * 3)
+                .map(|_| rng.generate::<f32>())
+                .collect(),
+        };
+
+        let initial_cells = match (&snapshot, &init_image) {
+            (Some(snapshot), _) => Some(snapshot.cells.clone()),
+            (None, Some(image)) => {
+                let colors = image.resample(cwidth, cheihgt);
+                if init_palette == ImagePalette::Image {
+                    // an image with fewer colors keeps the random ones after its own
+                    let palette = rgb8_to_colormap(&init::median_cut(&colors, color_number));
+                    colormap[..palette.len()].copy_from_slice(&palette);
+                }
+                Some(init::quantize(&colors, &colormap_to_rgb8(&colormap)))
+            }
+            (None, None) => None,
+        };
         Self {
             width: w,
-            height: w,
+            height: h,
 
             cell_dimension,
             cell_number_x: cwidth,
@@ -50,33 +195,460 @@ impl AppState {
 
             total_cell_number: real_cell_number,
             color_number,
-            colormap: (0..color_number * 3)
-                .map(|_| rng.generate::<f32>())
-                .collect(),
+            colormap,
+
+            // by default, as many enemies as there are colors are needed (if the neighborhood is big enough)
+            threshold: threshold.unwrap_or(color_number.min(neighborhood.len() as u32)..=u32::MAX),
+            neighborhood,
+            boundary,
+
+            seed,
+
+            steps_per_frame,
+            fast,
+            present_mode,
+            fps,
+            // big grids have many cells per pixel, drawing each of them is a waste
+            renderer: renderer.unwrap_or(
+                if filter == Some(Filter::Smooth) || real_cell_number >= FULLSCREEN_MIN_CELLS {
+                    Renderer::Fullscreen
+                } else {
+                    Renderer::Quads
+                },
+            ),
+            filter: filter.unwrap_or(Filter::Nearest),
+            regenerate_on_resize,
+
+            screenshot_at,
+            screenshot_mode,
+            screenshot_path,
+
+            record_path,
+            record_at_start,
+            record_frames,
+            record_every,
+
+            dump_frames,
+            dump_format,
+            pipe_raw,
+
+            initial_generation: snapshot.as_ref().map_or(0, |s| s.generation as usize),
+            initial_cells,
+            init: init.unwrap_or(Pattern::Random),
+            color_draw,
+            init_image,
+
+            population_path,
+            save_at,
+            save_path,
+        }
+    }
+
+    /// Lay out the same grid in a `width` x `height` window, scaled to fit and letterboxed
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.cell_dimension = (width as f32 / self.cell_number_x as f32)
+            .min(height as f32 / self.cell_number_y as f32);
+    }
+
+    /// Replace the grid by one filling a `width` x `height` window with cells of the same size
+    pub fn regenerate(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.cell_number_x = ((width as f32 / self.cell_dimension) as u32).max(1);
+        self.cell_number_y = ((height as f32 / self.cell_dimension) as u32).max(1);
+        self.total_cell_number = self.cell_number_x * self.cell_number_y;
+        // a loaded snapshot doesn't fit anymore, unlike an image that can be sampled again
+        self.initial_cells = self.init_image.as_ref().map(|image| {
+            let colors = image.resample(self.cell_number_x, self.cell_number_y);
+            init::quantize(&colors, &colormap_to_rgb8(&self.colormap))
+        });
+        self.initial_generation = 0;
+    }
+
+    /// Random generator of the initial cells, a different stream than the colormap one
+    pub fn cells_rng(&self) -> WyRand {
+        WyRand::new_seed(self.seed.wrapping_add(1))
+    }
+
+    /// The rule of the automaton, as run by the compute shader
+    pub fn rule(&self) -> Rule {
+        Rule {
+            colors: self.color_number,
+            threshold: self.threshold.clone(),
+            neighborhood: self.neighborhood.clone(),
+            boundary: self.boundary,
+        }
+    }
+
+    /// Content of the 'SimParams' uniform shared by the compute and draw shaders
+    pub fn sim_params(&self) -> Vec<u32> {
+        let mut params = vec![
+            self.cell_number_x,
+            self.cell_number_y,
+            self.total_cell_number,
+            self.color_number,
+            *self.threshold.start(),
+            *self.threshold.end(),
+            self.neighborhood.len() as u32,
+        ];
+        params.extend(self.boundary.as_params());
+        params
+    }
+
+    /// Content of the 'Layout' uniform of the draw shader: where the grid is in the window (in clip space)
+    pub fn layout(&self) -> Vec<f32> {
+        // size of a cell, x and y differ when the window isn't a square
+        let cell_size = [
+            2.0 * self.cell_dimension / self.width as f32,
+            2.0 * self.cell_dimension / self.height as f32,
+        ];
+        // the grid may not fill the whole window, it's centered
+        let origin = [
+            -0.5 * cell_size[0] * self.cell_number_x as f32,
+            -0.5 * cell_size[1] * self.cell_number_y as f32,
+        ];
+        vec![cell_size[0], cell_size[1], origin[0], origin[1]]
+    }
+
+    /// Cell (x, y) under `position`, given in clip space like in the draw shader,
+    /// it may be outside of the grid
+    pub fn cell_at(&self, position: [f32; 2]) -> (i32, i32) {
+        let [cell_size_x, cell_size_y, origin_x, origin_y] = self.layout()[..] else {
+            unreachable!("the layout is made of a cell size and an origin")
+        };
+        (
+            ((position[0] - origin_x) / cell_size_x).floor() as i32,
+            ((position[1] - origin_y) / cell_size_y).floor() as i32,
+        )
+    }
+}
+
+/// How fast the simulation goes, relative to the frames being displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// n generations each frame
+    PerFrame(u32),
+    /// one generation every n frames
+    EveryFrames(u32),
+}
+
+/// the fastest and slowest speeds reachable with the keyboard
+const MAX_GENERATIONS_PER_FRAME: u32 = 1 << 12;
+const MAX_FRAMES_PER_GENERATION: u32 = 1 << 7;
+
+impl Speed {
+    /// Twice as fast
+    pub fn faster(self) -> Self {
+        match self {
+            Speed::PerFrame(n) if n >= MAX_GENERATIONS_PER_FRAME => self,
+            Speed::PerFrame(n) => Speed::PerFrame((n * 2).min(MAX_GENERATIONS_PER_FRAME)),
+            Speed::EveryFrames(n) if n <= 2 => Speed::PerFrame(1),
+            Speed::EveryFrames(n) => Speed::EveryFrames(n / 2),
+        }
+    }
+
+    /// Twice as slow
+    pub fn slower(self) -> Self {
+        match self {
+            Speed::PerFrame(n) if n <= 1 => Speed::EveryFrames(2),
+            Speed::PerFrame(n) => Speed::PerFrame(n / 2),
+            Speed::EveryFrames(n) => Speed::EveryFrames((n * 2).min(MAX_FRAMES_PER_GENERATION)),
+        }
+    }
+}
+
+impl std::fmt::Display for Speed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Speed::PerFrame(n) => write!(f, "{n} gen/frame"),
+            Speed::EveryFrames(n) => write!(f, "1 gen/{n} frames"),
+        }
+    }
+}
+
+/// Whether the simulation runs, and how fast
+#[derive(Debug)]
+pub struct Playback {
+    pub paused: bool,
+    pub speed: Speed,
+    /// generations asked one by one, computed even when paused
+    pending_steps: u32,
+    /// frames displayed since the start, used by the slow speeds
+    frame_count: u64,
+}
+
+impl Playback {
+    pub fn new(speed: Speed) -> Self {
+        Self {
+            paused: false,
+            speed,
+            pending_steps: 0,
+            frame_count: 0,
+        }
+    }
+
+    /// Pause and advance exactly one generation
+    pub fn step_once(&mut self) {
+        self.paused = true;
+        self.pending_steps += 1;
+    }
+
+    /// Number of generations to compute before displaying the next frame
+    pub fn generations_for_next_frame(&mut self) -> u32 {
+        self.frame_count += 1;
+
+        let generations = match (self.paused, self.speed) {
+            (true, _) => 0,
+            (false, Speed::PerFrame(n)) => n,
+            (false, Speed::EveryFrames(n)) => self.frame_count.is_multiple_of(n as u64) as u32,
+        };
+        generations + std::mem::take(&mut self.pending_steps)
+    }
+}
+
+/// the most zoomed in the camera can be, relative to the whole grid view
+const MAX_ZOOM: f32 = 256.0;
+
+/// Which part of the grid is displayed, it only changes the rendering
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    /// point of the whole grid view (in clip space) displayed at the center of the window
+    pub center: [f32; 2],
+    /// how many times bigger than in the whole grid view things are
+    pub zoom: f32,
+}
+
+impl Camera {
+    /// The whole grid view
+    pub fn new() -> Self {
+        Self {
+            center: [0.0, 0.0],
+            zoom: 1.0,
+        }
+    }
+
+    /// Position in the whole grid view of what is displayed at `position`, both in clip space
+    pub fn unproject(&self, position: [f32; 2]) -> [f32; 2] {
+        [
+            position[0] / self.zoom + self.center[0],
+            position[1] / self.zoom + self.center[1],
+        ]
+    }
+
+    /// Zoom by `factor`, what's displayed at `position` (in clip space) staying in place
+    pub fn zoom_at(&mut self, position: [f32; 2], factor: f32) {
+        let anchor = self.unproject(position);
+        self.zoom = (self.zoom * factor).clamp(1.0, MAX_ZOOM);
+        self.center = [
+            anchor[0] - position[0] / self.zoom,
+            anchor[1] - position[1] / self.zoom,
+        ];
+    }
+
+    /// Move what's displayed by `delta`, in clip space
+    pub fn pan(&mut self, delta: [f32; 2]) {
+        self.center[0] -= delta[0] / self.zoom;
+        self.center[1] -= delta[1] / self.zoom;
+    }
+
+    /// Content of the 'Camera' uniform of the draw shader
+    pub fn uniform(&self) -> Vec<f32> {
+        // the last float pads the struct to 16 bytes
+        vec![self.center[0], self.center[1], self.zoom, 0.0]
+    }
+}
+
+/// the biggest brush reachable with the scroll wheel
+const MAX_BRUSH_RADIUS: u32 = 64;
+
+/// What the mouse paints on the grid
+#[derive(Debug)]
+pub struct Brush {
+    pub color: u32,
+    /// cells at an euclidean distance of at most radius from the cursor are painted
+    pub radius: u32,
+    /// last painted cell, while the left button is held
+    pub stroke: Option<(i32, i32)>,
+}
+
+impl Brush {
+    pub fn new() -> Self {
+        Self {
+            color: 0,
+            radius: 2,
+            stroke: None,
         }
     }
+
+    pub fn grow(&mut self) {
+        self.radius = (self.radius + 1).min(MAX_BRUSH_RADIUS);
+    }
+
+    pub fn shrink(&mut self) {
+        self.radius = self.radius.saturating_sub(1);
+    }
+
+    /// Continue the stroke up to `cell`, returns the cells to paint so that
+    /// there's no gap when the cursor moved by more than a cell since the last one
+    pub fn stroke_to(&mut self, cell: (i32, i32)) -> Vec<(i32, i32)> {
+        let Some((x0, y0)) = self.stroke.replace(cell) else {
+            return vec![cell];
+        };
+        let steps = (cell.0 - x0).abs().max((cell.1 - y0).abs()).max(1);
+        (1..=steps)
+            .map(|i| {
+                let t = i as f32 / steps as f32;
+                (
+                    x0 + ((cell.0 - x0) as f32 * t).round() as i32,
+                    y0 + ((cell.1 - y0) as f32 * t).round() as i32,
+                )
+            })
+            .collect()
+    }
 }
 
+/// with less than 2 colors, a cell would be its own enemy
+pub const MIN_COLOR_NUMBER: u32 = 2;
+/// the shader handles any number of colors, but the colormap is bound to the draw shader so keep it small
+pub const MAX_COLOR_NUMBER: u32 = 4096;
+
 const HELP: &str = "\
 SAXRUMFEX
 
 USAGE:
   saxrumfex --window_size NUMBER --cell_number NUMBER --color_number NUMBER
+  saxrumfex --headless --generations NUMBER --output FILE [--output-every NUMBER]
 
 FLAGS:
   -h, --help            Prints help information
+  --headless            Runs the simulation without any window, then writes the final grid to disk
 
 OPTIONS:
   --window_size  NUMBER - Sets window's width and height [default: 900px]
-  --cell_number  NUMBER - Sets the number of cells in the simulation [default: 1000]
-  --color_number NUMBER - Sets the number of distincs colors that a cell can takes [default: 3; random colors]
+  --width        NUMBER - Sets window's width, overrides 'window_size' [default: window_size]
+  --height       NUMBER - Sets window's height, overrides 'window_size' [default: window_size]
+  --cell_number  NUMBER - Sets the approximate number of cells in the simulation, at most 33554432 [default: 1000]
+  --grid         WxH    - Sets the exact number of cells in each direction, overrides 'cell_number' (e.g. 160x90)
+                          the grid is then scaled to fit in the window, so the simulation doesn't depend on its size
+  --cells-x      NUMBER - Same as 'grid', the number of cells in the y direction following the window's aspect ratio
+  --cells-y      NUMBER - Same as 'grid', the number of cells in the x direction following the window's aspect ratio
+                          (both 'cells-x' and 'cells-y' is the same as 'grid')
+  --seed         NUMBER - Sets the seed of the colormap and initial cells, to reproduce a run [default: random]
+  --steps-per-frame NUMBER - Sets the number of generations computed between two frames [default: 1]
+  --fast                - Runs as fast as possible (no frame rate limit nor vsync), only every 'steps-per-frame'
+                          generation is rendered
+  --present-mode NAME   - Sets how frames are presented, 'fifo' (vsync), 'mailbox' or 'immediate', fifo if unsupported
+                          [default: the best supported mode with vsync, or without it with 'fast']
+  --fps          NUMBER - Sets the targeted frame rate, 0 for unlimited (ignored with 'fast') [default: 60]
+  --regenerate-on-resize - Starts over with a grid filling the window when it's resized, instead of scaling the
+                          current grid to fit in it
+  --renderer     NAME   - Sets how the cells are drawn, 'quads' (a square per cell) or 'fullscreen' (a lookup per pixel)
+                          [default: fullscreen from 262144 cells, quads otherwise]
+  --filter       NAME   - Sets how the fullscreen renderer colors a pixel, 'nearest' (the cell under it) or 'smooth'
+                          (blending the cells around it) [default: nearest]
+  --color_number NUMBER - Sets the number of distincs colors that a cell can takes, from 2 to 4096 [default: 3; random colors]
+  --threshold    NUMBER - Sets the minimum number of enemies needed to take over a cell [default: the number of colors,
+                          at most the size of the neighborhood]
+                 MIN..=MAX - Or the range of number of enemies needed to take over a cell
+  --neighborhood NAME   - Sets the neighborhood of a cell, 'moore', 'von-neumann' or 'circular' [default: moore]
+  --radius       NUMBER - Sets the radius of the neighborhood, at most 1000 [default: 1]
+  --kernel       FILE   - Reads the neighborhood from a mask instead, a grid of '#' (neighbor) and '.' with the cell at its center
+  --boundary     NAME   - Sets what the neighbors outside of the grid are [default: clip]
+                          'clip' (ignored), 'wrap' (torus), 'reflect' (mirrored grid) or 'fixed:<color>' (all of this color)
+  --screenshot-at   NUMBER - Takes a screenshot once this generation is reached, like pressing F12
+  --screenshot-mode NAME   - Sets what screenshots show, 'raw' (one pixel per cell) or 'rendered' (the window at its
+                             resolution) [default: raw]
+  --screenshot-file FILE   - Sets where screenshots are written, as PNG images numbered with their generation
+                             [default: saxrumfex.png, written as saxrumfex_<generation>.png]
+  --record       FILE   - Records the generations from the start as an animation, GIF or APNG depending on the
+                          extension ('.gif', '.png' or '.apng'), also where R records [default: saxrumfex.gif,
+                          or saxrumfex.png above 256 colors, numbered as saxrumfex_<generation>.gif/.png when R is pressed]
+  --frames       NUMBER - Sets the number of frames of a recording [default: 100]
+  --every        NUMBER - Sets the number of generations between two frames of a recording or a dump [default: 1]
+  --dump-frames  DIR    - Writes a frame every 'every' generations in DIR, as numbered images (frame_000000.png, ...)
+                          showing the same as screenshots (see 'screenshot-mode', always raw in headless mode)
+  --dump-format  NAME   - Sets the format of the dumped frames, 'png' or 'ppm' [default: png]
+  --pipe-raw            - Writes the same frames as raw RGBA pixels on stdout, their dimensions being told on stderr
+  --init         NAME   - Sets the first generation, the same for a given seed [default: random]
+                          'random' (each cell of a random color), 'stripes[:<width>]' (vertical bands),
+                          'rings[:<width>]' (around the center), 'sectors[:<repeat>]' (a wedge per color, spawns
+                          spirals), 'spiral[:<arms>]', 'single-seed[:<radius>]' (a disk of the second color in the
+                          first one), 'quadrants' or 'noise:<density>' (the first color sprinkled with the others)
+  --color-weights W1,W2,... - Sets the relative density of each color in the random cells (e.g. 0.5,0.3,0.2)
+                          [default: uniform]
+  --background   COLOR[:DENSITY] - Covers the grid with COLOR but for sparse random cells of the other colors,
+                          DENSITY being their proportion [default: none; 0.01]
+  --init-image   FILE   - Seeds the grid from a PNG or PPM image, resampled to the grid and each cell taking the
+                          nearest color of the palette [default: the 'init' pattern]
+  --init-palette NAME   - Sets the palette of 'init-image', 'colormap' (the random colors) or 'image' (the most
+                          representative colors of the image, replacing the colormap) [default: colormap]
+  --load         FILE   - Resumes the simulation saved in FILE, its grid, rule, colormap and seed replacing the options
+  --save-at      NUMBER - Saves a snapshot once this generation is reached, like pressing F5
+  --save-file    FILE   - Sets where snapshots are written, numbered with their generation
+                          [default: saxrumfex.snapshot, written as saxrumfex_<generation>.snapshot]
+  --population   FILE   - Counts the cells of each color at every generation (on the gpu), written to FILE as CSV,
+                          numbered as FILE_<n>.csv for the n-th grid regenerated on resize
+
+HEADLESS OPTIONS:
+  --generations  NUMBER - Number of generations to compute, from the loaded one if any [default: 1000]
+  --output       FILE   - Where to write the final grid, as an image with one pixel per cell, PNG if FILE ends with
+                          '.png' and PPM otherwise [default: saxrumfex.ppm]
+  --output-every NUMBER - Also writes the grid every NUMBER generations, next to the final one (FILE_<generation>.ppm)
 ";
 
 #[derive(Debug)]
 pub struct AppArgs {
-    pub window_size: u32,
+    /// window width (in px)
+    pub width: u32,
+    /// window height (in px)
+    pub height: u32,
     cell_number: u32,
+    grid: Option<(u32, u32)>,
     color_number: u32,
+    threshold: Option<RangeInclusive<u32>>,
+    neighborhood: Vec<(i32, i32)>,
+    boundary: Boundary,
+    seed: Option<u64>,
+    steps_per_frame: u32,
+    fast: bool,
+    present_mode: Option<wgpu::PresentMode>,
+    fps: Option<f32>,
+    renderer: Option<Renderer>,
+    filter: Option<Filter>,
+    regenerate_on_resize: bool,
+    screenshot_at: Option<usize>,
+    screenshot_mode: ScreenshotMode,
+    screenshot_path: PathBuf,
+    record_path: PathBuf,
+    record_at_start: bool,
+    record_frames: u32,
+    record_every: u32,
+    dump_frames: Option<PathBuf>,
+    dump_format: ImageFormat,
+    pipe_raw: bool,
+    /// simulation to resume
+    snapshot: Option<Snapshot>,
+    save_at: Option<usize>,
+    save_path: PathBuf,
+    init: Option<Pattern>,
+    color_draw: ColorDraw,
+    init_image: Option<Image>,
+    init_palette: ImagePalette,
+    population_path: Option<PathBuf>,
+    /// set when the simulation must run without any window
+    pub headless: Option<HeadlessArgs>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeadlessArgs {
+    /// number of generations to compute
+    pub generations: u32,
+    /// image where the final grid is written
+    pub output: PathBuf,
+    /// if set, the grid is also written every 'output_every' generations
+    pub output_every: Option<u32>,
 }
 
 impl AppArgs {
@@ -89,27 +661,416 @@ impl AppArgs {
             std::process::exit(0);
         }
 
-        let args = Self {
-            window_size: pargs
-                .opt_value_from_fn("--window_size", |s| {
-                    s.parse::<u32>()
-                        .map_err(|_| "'Window size' should be a valid number")
-                })?
-                .unwrap_or(900),
+        let radius = pargs
+            .opt_value_from_fn("--radius", |s| match s.parse::<u32>() {
+                Ok(r) if r > 0 && r <= rule::MAX_RADIUS => Ok(r),
+                _ => Err(format!(
+                    "'Radius' should be a strictly positive number, at most {}",
+                    rule::MAX_RADIUS
+                )),
+            })?
+            .unwrap_or(1);
+        let neighborhood = match pargs.opt_value_from_os_str("--kernel", |s| {
+            Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+        })? {
+            Some(path) => fs::read_to_string(&path)
+                .map_err(|err| format!("Failed to read {}: {err}", path.display()))
+                .and_then(|mask| rule::parse_kernel(&mask))
+                .map_err(|cause| pico_args::Error::ArgumentParsingFailed { cause })?,
+            None => pargs
+                .opt_value_from_str::<_, Neighborhood>("--neighborhood")?
+                .unwrap_or(Neighborhood::Moore)
+                .offsets(radius),
+        };
+
+        let threshold = pargs.opt_value_from_fn("--threshold", parse_threshold)?;
+        if let Some(threshold) = &threshold {
+            // otherwise no cell could ever be taken over
+            if *threshold.start() as usize > neighborhood.len() {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: format!(
+                        "'Threshold' can't be greater than the size of the neighborhood ({})",
+                        neighborhood.len()
+                    ),
+                });
+            }
+        }
+
+        let window_size = pargs
+            .opt_value_from_fn("--window_size", parse_window_length)?
+            .unwrap_or(900);
+
+        let mut args = Self {
+            width: pargs
+                .opt_value_from_fn("--width", parse_window_length)?
+                .unwrap_or(window_size),
+            height: pargs
+                .opt_value_from_fn("--height", parse_window_length)?
+                .unwrap_or(window_size),
             cell_number: pargs
-                .opt_value_from_fn("--cell_number", |s| {
-                    s.parse::<u32>()
-                        .map_err(|_| "'Cell number' should be a valid number")
+                .opt_value_from_fn("--cell_number", |s| match s.parse::<u32>() {
+                    Ok(n) if n > 0 && n <= max_cell_number() => Ok(n),
+                    _ => Err(format!(
+                        "'Cell number' should be a strictly positive number, at most {}",
+                        max_cell_number()
+                    )),
                 })?
                 .unwrap_or(1000),
+            grid: pargs.opt_value_from_fn("--grid", parse_grid)?,
             color_number: pargs
-                .opt_value_from_fn("--color_number", |s| {
-                    s.parse::<u32>()
-                        .map_err(|_| "'Color number' should be a valid number")
+                .opt_value_from_fn("--color_number", |s| match s.parse::<u32>() {
+                    Ok(n) if (MIN_COLOR_NUMBER..=MAX_COLOR_NUMBER).contains(&n) => Ok(n),
+                    _ => Err(format!(
+                        "'Color number' should be a number between {MIN_COLOR_NUMBER} and {MAX_COLOR_NUMBER}"
+                    )),
                 })?
                 .unwrap_or(3),
+            threshold,
+            neighborhood,
+            boundary: pargs
+                .opt_value_from_str("--boundary")?
+                .unwrap_or(Boundary::Clip),
+            seed: pargs.opt_value_from_fn("--seed", |s| {
+                s.parse::<u64>()
+                    .map_err(|_| "'Seed' should be a valid unsigned 64 bits number")
+            })?,
+            steps_per_frame: pargs
+                .opt_value_from_fn("--steps-per-frame", |s| match s.parse::<u32>() {
+                    Ok(n) if n > 0 => Ok(n),
+                    _ => Err("'Steps per frame' should be a strictly positive number"),
+                })?
+                .unwrap_or(1),
+            fast: pargs.contains("--fast"),
+            present_mode: pargs.opt_value_from_fn("--present-mode", parse_present_mode)?,
+            // 0 means unlimited
+            fps: pargs
+                .opt_value_from_fn("--fps", |s| match s.parse::<f32>() {
+                    Ok(fps) if fps >= 0.0 && fps.is_finite() => Ok(fps),
+                    _ => Err("'Fps' should be a positive number"),
+                })?
+                .or(Some(60.0))
+                .filter(|&fps| fps > 0.0),
+            renderer: pargs.opt_value_from_str("--renderer")?,
+            filter: pargs.opt_value_from_str("--filter")?,
+            regenerate_on_resize: pargs.contains("--regenerate-on-resize"),
+            screenshot_at: pargs.opt_value_from_fn("--screenshot-at", |s| {
+                s.parse::<usize>()
+                    .map_err(|_| "'Screenshot at' should be a positive number")
+            })?,
+            screenshot_mode: pargs
+                .opt_value_from_str("--screenshot-mode")?
+                .unwrap_or(ScreenshotMode::Raw),
+            screenshot_path: pargs
+                .opt_value_from_os_str("--screenshot-file", |s| {
+                    Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+                })?
+                .unwrap_or_else(|| PathBuf::from("saxrumfex.png")),
+            record_path: PathBuf::from("saxrumfex.gif"),
+            record_at_start: false,
+            record_frames: pargs
+                .opt_value_from_fn("--frames", |s| match s.parse::<u32>() {
+                    Ok(n) if n > 0 => Ok(n),
+                    _ => Err("'Frames' should be a strictly positive number"),
+                })?
+                .unwrap_or(100),
+            record_every: pargs
+                .opt_value_from_fn("--every", |s| match s.parse::<u32>() {
+                    Ok(n) if n > 0 => Ok(n),
+                    _ => Err("'Every' should be a strictly positive number"),
+                })?
+                .unwrap_or(1),
+            dump_frames: pargs.opt_value_from_os_str("--dump-frames", |s| {
+                Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+            })?,
+            dump_format: pargs
+                .opt_value_from_str("--dump-format")?
+                .unwrap_or(ImageFormat::Png),
+            pipe_raw: pargs.contains("--pipe-raw"),
+            snapshot: None,
+            save_at: pargs.opt_value_from_fn("--save-at", |s| {
+                s.parse::<usize>()
+                    .map_err(|_| "'Save at' should be a positive number")
+            })?,
+            save_path: pargs
+                .opt_value_from_os_str("--save-file", |s| {
+                    Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+                })?
+                .unwrap_or_else(|| PathBuf::from("saxrumfex.snapshot")),
+            init: pargs.opt_value_from_str("--init")?,
+            color_draw: ColorDraw {
+                weights: pargs.opt_value_from_fn("--color-weights", parse_color_weights)?,
+                background: pargs.opt_value_from_fn("--background", parse_background)?,
+            },
+            init_image: None,
+            init_palette: pargs
+                .opt_value_from_str("--init-palette")?
+                .unwrap_or(ImagePalette::Colormap),
+            population_path: pargs.opt_value_from_os_str("--population", |s| {
+                Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+            })?,
+            headless: None,
+        };
+
+        if let Some(path) = pargs.opt_value_from_os_str("--init-image", |s| {
+            Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+        })? {
+            let image =
+                Image::read(&path).map_err(|err| pico_args::Error::ArgumentParsingFailed {
+                    cause: format!("Failed to read {}: {err}", path.display()),
+                })?;
+            args.init_image = Some(image);
+
+            if args.init.is_some() {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: "'Init' and 'init image' can't be used together".into(),
+                });
+            }
+        }
+
+        // the snapshot replaces everything about the simulation itself
+        if let Some(path) = pargs.opt_value_from_os_str("--load", |s| {
+            Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+        })? {
+            let snapshot =
+                Snapshot::read(&path).map_err(|err| pico_args::Error::ArgumentParsingFailed {
+                    cause: format!("Failed to load {}: {err}", path.display()),
+                })?;
+            args.grid = Some((snapshot.cell_number_x, snapshot.cell_number_y));
+            args.color_number = snapshot.color_number;
+            args.threshold = Some(snapshot.threshold.clone());
+            args.neighborhood = snapshot.neighborhood.clone();
+            args.boundary = snapshot.boundary;
+            args.seed = Some(snapshot.seed);
+            args.snapshot = Some(snapshot);
+
+            if args.init.is_some() || args.init_image.is_some() {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: "'Init' and 'init image' can't be used when loading a snapshot".into(),
+                });
+            }
+        }
+
+        if let Some(path) = pargs.opt_value_from_os_str("--record", |s| {
+            Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+        })? {
+            args.record_path = path;
+            args.record_at_start = true;
+        } else if !RecordFormat::Gif.supports_colors(args.color_number) {
+            // too many colors for the default GIF, not an error unless asked for
+            args.record_path.set_extension("png");
+        }
+        let record_format = RecordFormat::from_path(&args.record_path)
+            .map_err(|cause| pico_args::Error::ArgumentParsingFailed { cause })?;
+        if !record_format.supports_colors(args.color_number) {
+            return Err(pico_args::Error::ArgumentParsingFailed {
+                cause: "'Record' can't be a GIF with more than 256 colors, use an APNG instead"
+                    .into(),
+            });
+        }
+
+        // exact grid given one direction at a time
+        let cells_x = pargs.opt_value_from_fn("--cells-x", parse_cell_count)?;
+        let cells_y = pargs.opt_value_from_fn("--cells-y", parse_cell_count)?;
+        if args.grid.is_some() && (cells_x.is_some() || cells_y.is_some()) {
+            return Err(pico_args::Error::ArgumentParsingFailed {
+                cause:
+                    "'Cells x' and 'cells y' can't be used with 'grid' or when loading a snapshot"
+                        .into(),
+            });
+        }
+        if args.grid.is_none() {
+            let (w, h) = (args.width as f32, args.height as f32);
+            args.grid = match (cells_x, cells_y) {
+                (Some(cx), Some(cy)) => Some((cx, cy)),
+                (Some(cx), None) => Some((cx, ((cx as f32 * h / w).round() as u32).max(1))),
+                (None, Some(cy)) => Some((((cy as f32 * w / h).round() as u32).max(1), cy)),
+                (None, None) => None,
+            };
+        }
+        if let Some((cx, cy)) = args.grid {
+            // the cells buffers are bound whole to the shaders
+            if cx
+                .checked_mul(cy)
+                .is_none_or(|cells| cells > max_cell_number())
+            {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: format!("'Grid' can't have more than {} cells", max_cell_number()),
+                });
+            }
+        }
+
+        // kernels and snapshots aren't bound by the radius
+        if args.neighborhood.len() > max_neighborhood_size() {
+            return Err(pico_args::Error::ArgumentParsingFailed {
+                cause: format!(
+                    "The neighborhood can't have more than {} neighbors",
+                    max_neighborhood_size()
+                ),
+            });
+        }
+
+        if let Boundary::Fixed(color) = args.boundary {
+            if color >= args.color_number {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: format!(
+                        "'Boundary' color should be lower than the number of colors ({})",
+                        args.color_number
+                    ),
+                });
+            }
+        }
+
+        if let Some(weights) = &args.color_draw.weights {
+            if weights.len() != args.color_number as usize {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: format!(
+                        "'Color weights' should have a weight per color ({})",
+                        args.color_number
+                    ),
+                });
+            }
+        }
+        if let Some((color, _)) = args.color_draw.background {
+            if color >= args.color_number {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: format!(
+                        "'Background' color should be lower than the number of colors ({})",
+                        args.color_number
+                    ),
+                });
+            }
+        }
+        // the sparse cells are of any color but the background one, or the first one for noise
+        let sparse_excluded = match args.init {
+            Some(Pattern::Noise(_)) => Some(0),
+            _ => args.color_draw.background.map(|(color, _)| color),
         };
+        if let (Some(weights), Some(excluded)) = (&args.color_draw.weights, sparse_excluded) {
+            let others = weights
+                .iter()
+                .enumerate()
+                .filter(|&(color, _)| color != excluded as usize)
+                .map(|(_, weight)| weight)
+                .sum::<f32>();
+            if others <= 0.0 {
+                return Err(pico_args::Error::ArgumentParsingFailed {
+                    cause: format!(
+                        "'Color weights' should give a weight to a color other than the background one ({excluded})"
+                    ),
+                });
+            }
+        }
+
+        if args.renderer == Some(Renderer::Quads) && args.filter == Some(Filter::Smooth) {
+            return Err(pico_args::Error::ArgumentParsingFailed {
+                cause: "'Filter' can only be smooth with the fullscreen renderer".into(),
+            });
+        }
+
+        if pargs.contains("--headless") {
+            args.headless = Some(HeadlessArgs {
+                generations: pargs
+                    .opt_value_from_fn("--generations", |s| {
+                        s.parse::<u32>()
+                            .map_err(|_| "'Generations' should be a valid number")
+                    })?
+                    .unwrap_or(1000),
+                output: pargs
+                    .opt_value_from_os_str("--output", |s| {
+                        Ok::<_, std::convert::Infallible>(PathBuf::from(s))
+                    })?
+                    .unwrap_or_else(|| PathBuf::from("saxrumfex.ppm")),
+                output_every: pargs.opt_value_from_fn("--output-every", |s| {
+                    match s.parse::<u32>() {
+                        Ok(n) if n > 0 => Ok(n),
+                        _ => Err("'Output every' should be a strictly positive number"),
+                    }
+                })?,
+            });
+        }
 
         Ok(args)
     }
 }
+
+fn parse_window_length(s: &str) -> Result<u32, &'static str> {
+    match s.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err("'Window size', 'width' and 'height' should be strictly positive numbers"),
+    }
+}
+
+fn parse_cell_count(s: &str) -> Result<u32, &'static str> {
+    match s.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err("'Cells x' and 'cells y' should be strictly positive numbers"),
+    }
+}
+
+fn parse_present_mode(s: &str) -> Result<wgpu::PresentMode, &'static str> {
+    match s {
+        "fifo" => Ok(wgpu::PresentMode::Fifo),
+        "mailbox" => Ok(wgpu::PresentMode::Mailbox),
+        "immediate" => Ok(wgpu::PresentMode::Immediate),
+        _ => Err("'Present mode' should be one of 'fifo', 'mailbox' or 'immediate'"),
+    }
+}
+
+/// Parses "WxH", the number of cells in each direction
+fn parse_grid(s: &str) -> Result<(u32, u32), &'static str> {
+    let parsed = s
+        .split_once('x')
+        .map(|(w, h)| (w.parse::<u32>(), h.parse::<u32>()));
+    match parsed {
+        Some((Ok(w), Ok(h))) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err("'Grid' should be 'WxH', with W and H strictly positive numbers"),
+    }
+}
+
+/// Parses "W1,W2,...", the relative density of each color
+fn parse_color_weights(s: &str) -> Result<Vec<f32>, &'static str> {
+    const ERROR: &str =
+        "'Color weights' should be positive numbers separated by commas, not all 0 (e.g. 0.5,0.3,0.2)";
+
+    let weights = s
+        .split(',')
+        .map(|weight| match weight.trim().parse::<f32>() {
+            Ok(weight) if weight >= 0.0 && weight.is_finite() => Ok(weight),
+            _ => Err(ERROR),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if weights.iter().sum::<f32>() > 0.0 {
+        Ok(weights)
+    } else {
+        Err(ERROR)
+    }
+}
+
+/// Parses "COLOR[:DENSITY]", the proportion of seeds of other colors being 1% by default
+fn parse_background(s: &str) -> Result<(u32, f32), &'static str> {
+    const ERROR: &str = "'Background' should be 'COLOR' or 'COLOR:DENSITY', with 0 <= DENSITY <= 1";
+
+    let (color, density) = match s.split_once(':') {
+        Some((color, density)) => (color.parse::<u32>(), density.parse::<f32>()),
+        None => (s.parse::<u32>(), Ok(0.01)),
+    };
+    match (color, density) {
+        (Ok(color), Ok(density)) if (0.0..=1.0).contains(&density) => Ok((color, density)),
+        _ => Err(ERROR),
+    }
+}
+
+/// Parses either "N" (N enemies or more) or "MIN..=MAX"
+fn parse_threshold(s: &str) -> Result<RangeInclusive<u32>, &'static str> {
+    const ERROR: &str =
+        "'Threshold' should be a number or a range 'MIN..=MAX', with 0 < MIN <= MAX";
+
+    let (min, max) = match s.split_once("..=") {
+        Some((min, max)) => (min.parse::<u32>(), max.parse::<u32>()),
+        None => (s.parse::<u32>(), Ok(u32::MAX)),
+    };
+    match (min, max) {
+        (Ok(min), Ok(max)) if 0 < min && min <= max => Ok(min..=max),
+        _ => Err(ERROR),
+    }
+}
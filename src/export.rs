@@ -0,0 +1,210 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// What a screenshot shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotMode {
+    /// the grid, one pixel per cell
+    Raw,
+    /// what's in the window, at its resolution
+    Rendered,
+}
+
+impl FromStr for ScreenshotMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ScreenshotMode::Raw),
+            "rendered" => Ok(ScreenshotMode::Rendered),
+            _ => Err("'Screenshot mode' should be one of 'raw' or 'rendered'"),
+        }
+    }
+}
+
+/// Image formats of the dumped frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl FromStr for ImageFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(ImageFormat::Png),
+            "ppm" => Ok(ImageFormat::Ppm),
+            _ => Err("'Dump format' should be one of 'png' or 'ppm'"),
+        }
+    }
+}
+
+/// Where the frames of a video go: numbered images in a directory and/or raw RGBA pixels on stdout
+#[derive(Debug)]
+pub struct FrameOutput {
+    /// directory and format of the numbered images
+    dump: Option<(PathBuf, ImageFormat)>,
+    pipe: Option<io::Stdout>,
+    frames_written: usize,
+    /// dimensions of the last piped frame, told again when they change
+    piped_size: Option<(u32, u32)>,
+}
+
+impl FrameOutput {
+    /// None if the frames go nowhere, the directory is created if needed
+    pub fn new(dump: Option<(PathBuf, ImageFormat)>, pipe: bool) -> io::Result<Option<Self>> {
+        if dump.is_none() && !pipe {
+            return Ok(None);
+        }
+        if let Some((dir, _)) = &dump {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Some(Self {
+            dump,
+            pipe: pipe.then(io::stdout),
+            frames_written: 0,
+            piped_size: None,
+        }))
+    }
+
+    /// Writes the next frame, packed RGB pixels
+    pub fn write(&mut self, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+        if let Some((dir, format)) = &self.dump {
+            // zero padded, so that the files sort in order
+            let name = format!("frame_{:06}", self.frames_written);
+            match format {
+                ImageFormat::Png => write_png(&dir.join(name + ".png"), width, height, rgb)?,
+                ImageFormat::Ppm => write_ppm(&dir.join(name + ".ppm"), width, height, rgb)?,
+            }
+        }
+
+        if let Some(pipe) = &mut self.pipe {
+            // stdout only has the pixels, an encoder needs to be told their dimensions
+            if self.piped_size != Some((width, height)) {
+                eprintln!("Piping raw RGBA frames of {width}x{height}");
+                self.piped_size = Some((width, height));
+            }
+            let rgba = rgb
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], u8::MAX])
+                .collect::<Vec<_>>();
+            let mut pipe = pipe.lock();
+            pipe.write_all(&rgba)?;
+            pipe.flush()?;
+        }
+
+        self.frames_written += 1;
+        Ok(())
+    }
+}
+
+/// Converts a linear color component (as stored in the colormap) to an 8 bits sRGB one,
+/// the same conversion the gpu does when writing to the sRGB surface
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let srgb = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+/// Converts an 8 bits sRGB color component to a linear one, the inverse of `linear_to_srgb`
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts 8 bits sRGB triplets to colormap entries (3 linear f32 per color)
+pub fn rgb8_to_colormap(colors: &[[u8; 3]]) -> Vec<f32> {
+    colors
+        .iter()
+        .flatten()
+        .map(|&c| srgb_to_linear(c))
+        .collect()
+}
+
+/// Converts the colormap (3 linear f32 per color) to 8 bits sRGB triplets
+pub fn colormap_to_rgb8(colormap: &[f32]) -> Vec<[u8; 3]> {
+    colormap
+        .chunks_exact(3)
+        .map(|c| {
+            [
+                linear_to_srgb(c[0]),
+                linear_to_srgb(c[1]),
+                linear_to_srgb(c[2]),
+            ]
+        })
+        .collect()
+}
+
+/// Converts cells to packed RGB pixels, one pixel per cell
+///
+/// The first row of cells is drawn at the bottom of the window, so rows are flipped
+/// to get an image looking like what's on screen
+pub fn cells_to_rgb8(cells: &[u32], cell_number_x: u32, colormap: &[f32]) -> Vec<u8> {
+    let palette = colormap_to_rgb8(colormap);
+    cells
+        .chunks_exact(cell_number_x as usize)
+        .rev()
+        .flatten()
+        .flat_map(|&color| palette[color as usize])
+        .collect()
+}
+
+/// Writes packed RGB pixels as an image, PNG if `path` ends with '.png' and PPM otherwise
+pub fn write_image(path: &Path, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    let is_png = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    if is_png {
+        write_png(path, width, height, rgb)
+    } else {
+        write_ppm(path, width, height, rgb)
+    }
+}
+
+/// Writes packed RGB pixels as a PNG image
+pub fn write_png(path: &Path, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgb)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Writes packed RGB pixels as a binary PPM (P6) image
+pub fn write_ppm(path: &Path, width: u32, height: u32, rgb: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{width} {height}\n255\n")?;
+    file.write_all(rgb)?;
+    file.flush()
+}
+
+/// Error for a file whose content can't be read, snapshot or image
+pub fn invalid(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// "out.ppm" -> "out_<generation>.ppm"
+pub fn numbered_path(path: &Path, generation: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{stem}_{generation}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{generation}"),
+    };
+    path.with_file_name(file_name)
+}
@@ -1,11 +1,56 @@
-use std::{borrow::Cow, sync::Arc, time::Instant};
+use std::{borrow::Cow, str::FromStr, sync::Arc};
 
-use crate::app::AppState;
-use nanorand::{Rng, WyRand};
+use crate::{
+    app::{AppState, Camera},
+    simulation::{read_staging_buffer, Simulation, MAX_STEPS_PER_SUBMIT, SIZE_OF_U32},
+};
 use wgpu::{util::DeviceExt, TextureView};
 use winit::window::Window;
 
-const CELLS_PER_GROUP: u32 = 50; // lower is better perfomance, but too low is complete madness
+/// above this number of cells, the fullscreen renderer is the default one
+pub const FULLSCREEN_MIN_CELLS: u32 = 1 << 18;
+
+/// How the cells are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    /// one square per cell, placed by the vertex shader
+    Quads,
+    /// a single triangle covering the window, each pixel looking up the cell under it
+    Fullscreen,
+}
+
+impl FromStr for Renderer {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quads" => Ok(Renderer::Quads),
+            "fullscreen" => Ok(Renderer::Fullscreen),
+            _ => Err("'Renderer' should be one of 'quads' or 'fullscreen'"),
+        }
+    }
+}
+
+/// How the fullscreen renderer colors a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// the color of the cell under it
+    Nearest,
+    /// a blend of the four cells around it
+    Smooth,
+}
+
+impl FromStr for Filter {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Filter::Nearest),
+            "smooth" => Ok(Filter::Smooth),
+            _ => Err("'Filter' should be one of 'nearest' or 'smooth'"),
+        }
+    }
+}
 
 pub struct WgpuContext {
     /// winnit window representation
@@ -16,25 +61,27 @@ pub struct WgpuContext {
     /// parameters (texture,...) of the surface
     pub surface_config: wgpu::SurfaceConfiguration,
 
-    pub device: wgpu::Device,
-    queue: wgpu::Queue,
-
-    // bind groups
-    cells_compute_bind_groups: Vec<wgpu::BindGroup>,
-    draw_bind_groups: wgpu::BindGroup,
-
-    // buffers
-    cells_buffers: Vec<wgpu::Buffer>, // src and dst so only 2
-    vertices_buffer: wgpu::Buffer,    // contain square vertices
+    /// gpu device, queue and the cells being simulated
+    pub simulation: Simulation,
 
-    // pipelines
-    compute_pipeline: wgpu::ComputePipeline,
-    render_pipeline: wgpu::RenderPipeline,
+    /// what draws the cells
+    drawer: Drawer,
+    layout_buffer: wgpu::Buffer, // where the grid is in the window
+    camera_buffer: wgpu::Buffer, // which part of the grid is displayed
+}
 
-    // extra
-    work_group_count: u32,
-    pub frame_num: usize,
-    pub since_last_frame: Instant,
+enum Drawer {
+    /// one instance of a square per cell, the cells being its instance buffer
+    Quads {
+        draw_bind_groups: wgpu::BindGroup,
+        vertices_buffer: wgpu::Buffer, // contain square vertices
+        render_pipeline: wgpu::RenderPipeline,
+    },
+    /// a fullscreen triangle sampling the cells, one bind group for each cells buffer
+    Fullscreen {
+        draw_bind_groups: Vec<wgpu::BindGroup>,
+        render_pipeline: wgpu::RenderPipeline,
+    },
 }
 
 impl WgpuContext {
@@ -51,27 +98,9 @@ impl WgpuContext {
             .await
             .unwrap();
 
-        // check that compute shader are supported
-        let downlevel_capabilities = adapter.get_downlevel_capabilities();
-        assert!(
-            downlevel_capabilities
-                .flags
-                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS),
-            "Adapter does not support the downlevel capabilities required to run a compute shader"
-        );
-
-        // request device
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device descriptor"),
-                    required_features: wgpu::Features::empty(),
-                    required_limits: wgpu::Limits::downlevel_defaults(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        // request device and create the simulation itself, it owns the cells and the compute pipeline
+        let simulation = Simulation::new(&adapter, state).await;
+        let device = &simulation.device;
 
         // configure the surface now that we have a device
         let mut config = surface
@@ -89,122 +118,99 @@ impl WgpuContext {
             config.format = format;
             config.view_formats.push(format);
         };
-        surface.configure(&device, &config);
+        config.present_mode = match state.present_mode {
+            Some(present_mode) if surface_caps.present_modes.contains(&present_mode) => {
+                present_mode
+            }
+            Some(present_mode) => {
+                log::warn!("{present_mode:?} present mode isn't supported, falling back to fifo");
+                wgpu::PresentMode::Fifo
+            }
+            // don't wait for the screen refresh when running as fast as possible
+            None if state.fast => wgpu::PresentMode::AutoNoVsync,
+            None => wgpu::PresentMode::AutoVsync,
+        };
+        surface.configure(device, &config);
 
-        // fetch shaders
+        let (drawer, layout_buffer, camera_buffer) =
+            Self::create_drawer(&simulation, &config, state);
 
-        let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Compute shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("./shaders/compute.wgsl"))),
-        });
-        let draw_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Draw shader"),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("./shaders/draw.wgsl"))),
-        });
+        Self {
+            window,
 
-        // buffer for simulation parameters uniform
+            surface,
+            surface_config: config,
 
-        let sim_param_data = vec![
-            state.width,
-            state.height,
-            state.cell_dimension,
-            state.cell_number_x,
-            state.cell_number_y,
-            state.total_cell_number,
-            state.color_number,
-        ];
-        let sim_param_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Simulation Parameter Buffer"),
-            contents: bytemuck::cast_slice(&sim_param_data),
+            simulation,
+
+            drawer,
+            layout_buffer,
+            camera_buffer,
+        }
+    }
+
+    /// Create the renderer chosen by `state`, along with its layout and camera buffers
+    fn create_drawer(
+        simulation: &Simulation,
+        config: &wgpu::SurfaceConfiguration,
+        state: &AppState,
+    ) -> (Drawer, wgpu::Buffer, wgpu::Buffer) {
+        let device = &simulation.device;
+
+        // buffers shared by both renderers
+
+        let colormap_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Colormap Buffer"),
+            contents: bytemuck::cast_slice(&state.colormap),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Layout Buffer"),
+            contents: bytemuck::cast_slice(&state.layout()),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&Camera::new().uniform()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let draw_buffers = DrawBuffers {
+            sim_param: simulation.sim_param_buffer(),
+            colormap: &colormap_buffer,
+            layout: &layout_buffer,
+            camera: &camera_buffer,
+        };
 
-        // create compute bind layout group and compute pipeline layout
+        let drawer = match state.renderer {
+            Renderer::Quads => Self::create_quads_drawer(device, &draw_buffers, config),
+            Renderer::Fullscreen => Self::create_fullscreen_drawer(
+                device,
+                &draw_buffers,
+                simulation,
+                config,
+                state.filter,
+            ),
+        };
+        (drawer, layout_buffer, camera_buffer)
+    }
 
-        /// represent the size in bytes taken by an 'u32' in memory, which is 4
-        const SIZE_OF_U32: u64 = std::mem::size_of::<u32>() as u64;
-        let compute_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                (sim_param_data.len() as u64) * SIZE_OF_U32,
-                            ),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                // shader variable 'cellSrc' is of type array<u32> of len total_cell_number
-                                (state.total_cell_number as u64) * SIZE_OF_U32,
-                            ),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                // shader variable 'cellDst' is of type array<u32> of len total_cell_number
-                                (state.total_cell_number as u64) * SIZE_OF_U32,
-                            ),
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("Compute bind groups"),
-            });
-        let compute_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("compute pipeline layout"),
-                bind_group_layouts: &[&compute_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+    fn create_quads_drawer(
+        device: &wgpu::Device,
+        draw_buffers: &DrawBuffers,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Drawer {
+        // fetch shaders
+
+        let draw_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Draw shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("./shaders/draw.wgsl"))),
+        });
 
         // create render pipeline with bind groups
-        const SIZE_OF_F32: u64 = std::mem::size_of::<f32>() as u64;
+
         let render_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT, // only vertex need an access to global parameters
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                (sim_param_data.len() as u64) * SIZE_OF_U32,
-                            ),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT, // only the fragment need to have access to the cell color
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                // shader variable 'colormap' is of type array<[u32; u32; u32]> of len NUM_COLOR
-                                (state.color_number as u64) * 3 * SIZE_OF_F32,
-                            ),
-                        },
-                        count: None,
-                    },
-                ],
+                entries: &draw_buffers.layout_entries(),
                 label: Some("Render bind groups"),
             });
 
@@ -248,138 +254,240 @@ impl WgpuContext {
             multiview: None,
         });
 
-        // create compute pipeline
-
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Compute pipeline"),
-            layout: Some(&compute_pipeline_layout),
-            module: &compute_shader,
-            entry_point: "main",
-        });
-
         // buffer for the four 2d square vertices of each instance
 
         /// 6 points in ccw order: (-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)  representing a single square taking all screen
         const SQUARE_VERTEX: [f32; 12] = [
             -1.0, -1.0, 1.0, -1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0, 1.0, 1.0,
         ];
-        let min_number_of_cell = state.cell_number_x.min(state.cell_number_y);
 
-        // square scaled down by a factor of the minimum
-        let vertex_buffer_data = SQUARE_VERTEX.map(|coord| coord / min_number_of_cell as f32);
+        // the square is scaled down to the size of a cell by the draw shader
         let vertices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
-            contents: bytemuck::bytes_of(&vertex_buffer_data),
+            contents: bytemuck::bytes_of(&SQUARE_VERTEX),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
-        // buffer for all cell color
-
-        let mut rng = WyRand::new();
-        let initial_cell_data = (0..state.total_cell_number)
-            .map(|_| rng.generate_range(0_u32..state.color_number))
-            .collect::<Vec<u32>>();
-
-        // creates two buffers of cell data each of size total_cell_number
-        // the two buffers alternate as dst and src for each frame
-
-        let mut cells_buffers = Vec::<wgpu::Buffer>::new();
-        for i in 0..2 {
-            cells_buffers.push(
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("Cell Buffer {i}")),
-                    contents: bytemuck::cast_slice(&initial_cell_data),
-                    usage: wgpu::BufferUsages::VERTEX
-                        | wgpu::BufferUsages::STORAGE
-                        | wgpu::BufferUsages::COPY_DST,
-                }),
-            );
-        }
-
-        // create two bind groups, one for each buffer as the src
-        // where the alternate buffer is used as the dst
-
-        let mut cells_compute_bind_groups = Vec::<wgpu::BindGroup>::new();
-        for i in 0..2 {
-            cells_compute_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout: &compute_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: sim_param_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: cells_buffers[i].as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: cells_buffers[(i + 1) % 2].as_entire_binding(), // bind to opposite buffer
-                    },
-                ],
-                label: Some(&format!("compute bind group {i}")),
-            }));
-        }
-
         // bind group for draw shader
 
-        let colormap_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Colormap Buffer"),
-            contents: bytemuck::cast_slice(&state.colormap),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
         let draw_bind_groups = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &render_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: sim_param_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: colormap_buffer.as_entire_binding(),
-                },
-            ],
+            entries: &draw_buffers.entries(),
             label: Some("draw bind group"),
         });
 
-        // calculates number of work groups from CELLS_PER_GROUP constant
-        let work_group_count =
-            ((state.total_cell_number as f32) / (CELLS_PER_GROUP as f32)).ceil() as u32;
+        Drawer::Quads {
+            draw_bind_groups,
+            vertices_buffer,
+            render_pipeline,
+        }
+    }
 
-        Self {
-            window,
+    fn create_fullscreen_drawer(
+        device: &wgpu::Device,
+        draw_buffers: &DrawBuffers,
+        simulation: &Simulation,
+        config: &wgpu::SurfaceConfiguration,
+        filter: Filter,
+    ) -> Drawer {
+        let grid_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Grid shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("./shaders/grid.wgsl"))),
+        });
 
-            surface,
-            surface_config: config,
+        // same bindings as the quads, plus the cells themselves
+        let cells_size = simulation.current_cells_buffer().size();
+        let mut layout_entries = draw_buffers.layout_entries().to_vec();
+        layout_entries.push(wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::FRAGMENT, // the fragment looks up the cell under it
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                // shader variable 'cells' is of type array<u32> of len total_cell_number
+                min_binding_size: wgpu::BufferSize::new(cells_size),
+            },
+            count: None,
+        });
+        let render_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &layout_entries,
+                label: Some("Grid render bind groups"),
+            });
 
-            device,
-            queue,
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("grid render pipeline layout"),
+                bind_group_layouts: &[&render_bind_group_layout],
+                push_constant_ranges: &[],
+            });
 
-            cells_compute_bind_groups,
-            draw_bind_groups,
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("grid render pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &grid_shader,
+                entry_point: "main_vs",
+                buffers: &[], // the triangle is generated from the vertex index
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &grid_shader,
+                entry_point: match filter {
+                    Filter::Nearest => "main_fs_nearest",
+                    Filter::Smooth => "main_fs_smooth",
+                },
+                targets: &[Some(config.view_formats[0].into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
 
-            cells_buffers,
-            vertices_buffer,
+        // create two bind groups, one for each buffer being the current one
+        let draw_bind_groups = (0..2)
+            .map(|i| {
+                let mut entries = draw_buffers.entries().to_vec();
+                entries.push(wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: simulation.cells_buffer(i).as_entire_binding(),
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &render_bind_group_layout,
+                    entries: &entries,
+                    label: Some(&format!("grid draw bind group {i}")),
+                })
+            })
+            .collect();
 
-            compute_pipeline,
+        Drawer::Fullscreen {
+            draw_bind_groups,
             render_pipeline,
-
-            work_group_count,
-            frame_num: 0,
-            since_last_frame: Instant::now(),
         }
     }
 
-    pub fn render(&mut self, view: &TextureView, state: &AppState) {
+    /// Follow a new window size, the grid being laid out again by `state` but kept as it is
+    pub fn resize(&mut self, state: &AppState) {
+        self.surface_config.width = state.width;
+        self.surface_config.height = state.height;
+        self.surface
+            .configure(&self.simulation.device, &self.surface_config);
+        self.simulation.queue.write_buffer(
+            &self.layout_buffer,
+            0,
+            bytemuck::cast_slice(&state.layout()),
+        );
+    }
+
+    /// Start over with a new simulation of the grid described by `state`, for a new window size
+    ///
+    /// The camera is back to the whole grid view until it's set again
+    pub fn regenerate(&mut self, state: &AppState) {
+        self.simulation = self.simulation.restart(state);
+        self.surface_config.width = state.width;
+        self.surface_config.height = state.height;
+        self.surface
+            .configure(&self.simulation.device, &self.surface_config);
+        (self.drawer, self.layout_buffer, self.camera_buffer) =
+            Self::create_drawer(&self.simulation, &self.surface_config, state);
+    }
+
+    /// Draw the current generation again, at the window resolution, into an offscreen texture
+    /// and read it back as packed RGB pixels, the top row first
+    pub fn capture(&mut self, state: &AppState) -> Result<Vec<u8>, String> {
+        let format = self.surface_config.view_formats[0];
+        let bgra = match format.remove_srgb_suffix() {
+            wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8Unorm => true,
+            _ => return Err(format!("Can't capture a {format:?} surface")),
+        };
+
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self
+            .simulation
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Capture Texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+        self.render(
+            &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            state,
+            0,
+        );
+
+        // each row of the copy must be aligned
+        let bytes_per_row = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let staging_buffer = self
+            .simulation
+            .device
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Capture Staging Buffer"),
+                size: (bytes_per_row * height) as u64,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        let mut command_encoder = self
+            .simulation
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        command_encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        self.simulation.queue.submit(Some(command_encoder.finish()));
+
+        let data = read_staging_buffer(&self.simulation.device, &staging_buffer);
+        Ok(data
+            .chunks_exact(bytes_per_row as usize)
+            .flat_map(|row| row[..(width * 4) as usize].chunks_exact(4))
+            .flat_map(|p| {
+                if bgra {
+                    [p[2], p[1], p[0]]
+                } else {
+                    [p[0], p[1], p[2]]
+                }
+            })
+            .collect())
+    }
+
+    /// Display the part of the grid seen by `camera` from the next frame on
+    pub fn set_camera(&self, camera: &Camera) {
+        self.simulation.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&camera.uniform()),
+        );
+    }
+
+    /// Compute `generations` new generations (possibly none), then draw the cells
+    pub fn render(&mut self, view: &TextureView, state: &AppState, generations: u32) {
         // create render pass descriptor and its color attachments
         let color_attachments = [Some(wgpu::RenderPassColorAttachment {
             view,
             resolve_target: None,
             ops: wgpu::Operations {
-                // Not clearing here in order to test wgpu's zero texture initialization on a surface texture.
-                // Users should avoid loading uninitialized memory since this can cause additional overhead.
-                load: wgpu::LoadOp::Load,
+                // the grid may not cover the whole window (letterboxing)
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                 store: wgpu::StoreOp::Store,
             },
         })];
@@ -393,41 +501,91 @@ impl WgpuContext {
 
         // get command encoder
         let mut command_encoder = self
+            .simulation
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        command_encoder.push_debug_group("compute cell next frame");
-        {
-            // compute pass
-            let mut cpass = command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: None,
-                timestamp_writes: None,
-            });
-            cpass.set_pipeline(&self.compute_pipeline);
-            cpass.set_bind_group(0, &self.cells_compute_bind_groups[self.frame_num % 2], &[]);
-            cpass.dispatch_workgroups(self.work_group_count, 1, 1);
-        }
-        command_encoder.pop_debug_group();
+        // compute the next generations, the last batch being submitted along with the drawing
+        let last_batch = generations.min(MAX_STEPS_PER_SUBMIT);
+        self.simulation.steps(generations - last_batch);
+        self.simulation
+            .encode_steps(&mut command_encoder, last_batch);
 
         command_encoder.push_debug_group("draw cells");
         {
             // render pass
             let mut rpass = command_encoder.begin_render_pass(&render_pass_descriptor);
-            rpass.set_pipeline(&self.render_pipeline);
-            // uniforms!
-            rpass.set_bind_group(0, &self.draw_bind_groups, &[]);
-            // give the cell color
-            rpass.set_vertex_buffer(0, self.cells_buffers[(self.frame_num + 1) % 2].slice(..));
-            // the four instance-local vertices
-            rpass.set_vertex_buffer(1, self.vertices_buffer.slice(..));
-            rpass.draw(0..6, 0..state.total_cell_number);
+            match &self.drawer {
+                Drawer::Quads {
+                    draw_bind_groups,
+                    vertices_buffer,
+                    render_pipeline,
+                } => {
+                    rpass.set_pipeline(render_pipeline);
+                    // uniforms!
+                    rpass.set_bind_group(0, draw_bind_groups, &[]);
+                    // give the cell color
+                    rpass.set_vertex_buffer(0, self.simulation.current_cells_buffer().slice(..));
+                    // the four instance-local vertices
+                    rpass.set_vertex_buffer(1, vertices_buffer.slice(..));
+                    rpass.draw(0..6, 0..state.total_cell_number);
+                }
+                Drawer::Fullscreen {
+                    draw_bind_groups,
+                    render_pipeline,
+                } => {
+                    rpass.set_pipeline(render_pipeline);
+                    // uniforms and the cells of the current generation
+                    rpass.set_bind_group(0, &draw_bind_groups[self.simulation.generation % 2], &[]);
+                    rpass.draw(0..3, 0..1);
+                }
+            }
         }
         command_encoder.pop_debug_group();
 
-        // update frame count
-        self.frame_num += 1;
-
         // done
-        self.queue.submit(Some(command_encoder.finish()));
+        self.simulation.queue.submit(Some(command_encoder.finish()));
+    }
+}
+
+/// Buffers bound to both renderers, in this order
+struct DrawBuffers<'a> {
+    sim_param: &'a wgpu::Buffer,
+    colormap: &'a wgpu::Buffer,
+    layout: &'a wgpu::Buffer,
+    camera: &'a wgpu::Buffer,
+}
+
+impl DrawBuffers<'_> {
+    fn layout_entries(&self) -> [wgpu::BindGroupLayoutEntry; 4] {
+        let entry = |binding, ty, buffer: &wgpu::Buffer| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(buffer.size()),
+            },
+            count: None,
+        };
+        [
+            entry(0, wgpu::BufferBindingType::Uniform, self.sim_param),
+            // 'colormap' is an array<f32> of len 3 * number of colors
+            entry(
+                1,
+                wgpu::BufferBindingType::Storage { read_only: true },
+                self.colormap,
+            ),
+            entry(2, wgpu::BufferBindingType::Uniform, self.layout),
+            entry(3, wgpu::BufferBindingType::Uniform, self.camera),
+        ]
+    }
+
+    fn entries(&self) -> [wgpu::BindGroupEntry<'_>; 4] {
+        let buffers = [self.sim_param, self.colormap, self.layout, self.camera];
+        std::array::from_fn(|i| wgpu::BindGroupEntry {
+            binding: i as u32,
+            resource: buffers[i].as_entire_binding(),
+        })
     }
 }
@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use crate::{
+    app::{AppState, HeadlessArgs},
+    export::{cells_to_rgb8, numbered_path, write_image, FrameOutput},
+    population::PopulationLog,
+    record::Recorder,
+    simulation::Simulation,
+    snapshot,
+};
+
+/// Runs the simulation without any window nor surface, writing the grid(s) to disk
+pub async fn run(state: AppState, args: HeadlessArgs) {
+    // no surface to be compatible with, so any adapter (even a software one) will do
+    let instance = wgpu::Instance::default();
+    let adapter = match instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        })
+        .await
+    {
+        Some(adapter) => adapter,
+        None => instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: true,
+            })
+            .await
+            .expect("No adapter (not even a fallback one) is available"),
+    };
+    log::info!("Running headless on {:?}", adapter.get_info());
+
+    let mut simulation = Simulation::new(&adapter, &state).await;
+
+    let mut recorder = state.record_at_start.then(|| {
+        Recorder::new(
+            state.record_path.clone(),
+            &state,
+            simulation.generation,
+            // no more frames than generations to record
+            state
+                .record_frames
+                .min((args.generations / state.record_every).saturating_add(1)),
+            state.record_every,
+            false,
+        )
+        .unwrap_or_else(|err| panic!("Failed to record {}: {err}", state.record_path.display()))
+    });
+
+    // without any window, the frames are the raw grid
+    let mut frame_output = FrameOutput::new(
+        state
+            .dump_frames
+            .clone()
+            .map(|dir| (dir, state.dump_format)),
+        state.pipe_raw,
+    )
+    .unwrap_or_else(|err| panic!("Failed to dump frames: {err}"));
+    let every = state.record_every as usize;
+    let mut population_log = state.population_path.as_ref().map(|path| {
+        PopulationLog::new(path, state.color_number)
+            .unwrap_or_else(|err| panic!("Failed to write {}: {err}", path.display()))
+    });
+
+    // generations are computed in batches, up to the next grid to write or frame to record
+    // counted from the loaded generation when resuming a snapshot
+    let generations = simulation.generation + args.generations as usize;
+    loop {
+        if let Some(active) = recorder
+            .as_mut()
+            .filter(|recorder| recorder.next_generation() == simulation.generation)
+        {
+            let done = active
+                .record(&simulation.read_cells())
+                .unwrap_or_else(|err| panic!("Failed to record {}: {err}", active.path.display()));
+            if done {
+                finish_recording(recorder.take().unwrap());
+            }
+        }
+        if let Some(output) = frame_output
+            .as_mut()
+            .filter(|_| simulation.generation.is_multiple_of(every))
+        {
+            let cells = simulation.read_cells();
+            let rgb = cells_to_rgb8(&cells, state.cell_number_x, &state.colormap);
+            output
+                .write(state.cell_number_x, state.cell_number_y, &rgb)
+                .unwrap_or_else(|err| panic!("Failed to output frame: {err}"));
+        }
+        if state.save_at == Some(simulation.generation) {
+            let path = snapshot::save(&simulation, &state)
+                .unwrap_or_else(|err| panic!("Failed to write the snapshot: {err}"));
+            eprintln!("Snapshot written to {}", path.display());
+        }
+        if simulation.generation >= generations {
+            break;
+        }
+
+        let next_output = args
+            .output_every
+            .map(|every| (simulation.generation / every as usize + 1) * every as usize);
+        let next_stop = [
+            Some(generations),
+            next_output,
+            state.save_at.filter(|&at| at > simulation.generation),
+            recorder.as_ref().map(Recorder::next_generation),
+            frame_output
+                .as_ref()
+                .map(|_| (simulation.generation / every + 1) * every),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap();
+        simulation.steps((next_stop - simulation.generation) as u32);
+        if let Some(log) = &mut population_log {
+            log.write(&simulation.populations(false))
+                .unwrap_or_else(|err| panic!("Failed to write the populations: {err}"));
+        }
+
+        if next_output == Some(simulation.generation) {
+            let path = numbered_path(&args.output, simulation.generation);
+            write_grid(&simulation, &state, &path);
+        }
+    }
+    if let Some(log) = &mut population_log {
+        log.write(&simulation.populations(true))
+            .unwrap_or_else(|err| panic!("Failed to write the populations: {err}"));
+    }
+    // the frames fit in the generations, so the recording is finished by now
+    debug_assert!(recorder.is_none());
+
+    write_grid(&simulation, &state, &args.output);
+    eprintln!(
+        "{} generations computed, final grid (generation {}) written to {}",
+        args.generations,
+        simulation.generation,
+        args.output.display()
+    );
+}
+
+fn write_grid(simulation: &Simulation, state: &AppState, path: &Path) {
+    let cells = simulation.read_cells();
+    let rgb = cells_to_rgb8(&cells, state.cell_number_x, &state.colormap);
+    write_image(path, state.cell_number_x, state.cell_number_y, &rgb)
+        .unwrap_or_else(|err| panic!("Failed to write {}: {err}", path.display()));
+}
+
+fn finish_recording(recorder: Recorder) {
+    let path = recorder.path.clone();
+    recorder
+        .finish()
+        .unwrap_or_else(|err| panic!("Failed to write {}: {err}", path.display()));
+    eprintln!("Recording written to {}", path.display());
+}
@@ -0,0 +1,701 @@
+//! Initial conditions of the grid, procedural patterns or sampled from an image
+
+use std::{
+    f32::consts::TAU,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    str::FromStr,
+};
+
+use nanorand::{Rng, WyRand};
+
+use crate::export::invalid;
+
+/// Procedural first generations, parameters being optional with a default fitting the grid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// each cell of a random color
+    Random,
+    /// vertical bands of this many cells, cycling through the colors
+    Stripes(Option<u32>),
+    /// concentric rings of this many cells around the center, cycling through the colors
+    Rings(Option<u32>),
+    /// wedges around the center, one per color this many times
+    Sectors(u32),
+    /// a spiral around the center with this many arms
+    Spiral(u32),
+    /// the first color everywhere but a disk of this radius at the center, of the second color
+    SingleSeed(u32),
+    /// a quarter of the grid per color, cycling through the colors
+    Quadrants,
+    /// the first color, with this proportion of cells of a random other color
+    Noise(f32),
+}
+
+impl FromStr for Pattern {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: &str = "'Init' should be one of 'random', 'stripes[:<width>]', 'rings[:<width>]', \
+                             'sectors[:<repeat>]', 'spiral[:<arms>]', 'single-seed[:<radius>]', 'quadrants' \
+                             or 'noise:<density>', with strictly positive numbers (but the radius) and 0 < density <= 1";
+
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        // optional strictly positive parameter
+        let count = |param: Option<&str>| match param.map(str::parse::<u32>) {
+            None => Ok(None),
+            Some(Ok(n)) if n > 0 => Ok(Some(n)),
+            _ => Err(ERROR),
+        };
+
+        match (name, param) {
+            ("random", None) => Ok(Pattern::Random),
+            ("stripes", _) => Ok(Pattern::Stripes(count(param)?)),
+            ("rings", _) => Ok(Pattern::Rings(count(param)?)),
+            ("sectors", _) => Ok(Pattern::Sectors(count(param)?.unwrap_or(1))),
+            ("spiral", _) => Ok(Pattern::Spiral(count(param)?.unwrap_or(1))),
+            ("single-seed", _) => match param.map(str::parse::<u32>) {
+                None => Ok(Pattern::SingleSeed(0)),
+                Some(Ok(radius)) => Ok(Pattern::SingleSeed(radius)),
+                Some(Err(_)) => Err(ERROR),
+            },
+            ("quadrants", None) => Ok(Pattern::Quadrants),
+            ("noise", Some(density)) => match density.parse::<f32>() {
+                Ok(density) if density > 0.0 && density <= 1.0 => Ok(Pattern::Noise(density)),
+                _ => Err(ERROR),
+            },
+            _ => Err(ERROR),
+        }
+    }
+}
+
+impl Pattern {
+    /// Colors of the `cell_number_x` x `cell_number_y` cells, row by row from the bottom one,
+    /// the random ones drawn by `draw` from `rng`
+    pub fn cells(
+        self,
+        cell_number_x: u32,
+        cell_number_y: u32,
+        color_number: u32,
+        draw: &ColorDraw,
+        rng: &mut WyRand,
+    ) -> Vec<u32> {
+        let (cx, cy) = (cell_number_x as f32 / 2.0, cell_number_y as f32 / 2.0);
+        let color_of = |x: u32, y: u32, rng: &mut WyRand| -> u32 {
+            // from the center of the grid to the center of the cell
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            // in [0, 1), counterclockwise from the right
+            let turn = dy.atan2(dx).rem_euclid(TAU) / TAU;
+            let distance = dx.hypot(dy);
+
+            match self {
+                Pattern::Random => draw.color(color_number, rng),
+                Pattern::Stripes(width) => {
+                    let width = width.unwrap_or((cell_number_x / color_number).max(1));
+                    x / width % color_number
+                }
+                Pattern::Rings(width) => {
+                    // by default, a ring per color from the center to the smallest side
+                    let width = match width {
+                        Some(width) => width as f32,
+                        None => (cx.min(cy) / color_number as f32).max(1.0),
+                    };
+                    (distance / width) as u32 % color_number
+                }
+                Pattern::Sectors(repeat) => {
+                    (turn * color_number as f32 * repeat as f32) as u32 % color_number
+                }
+                Pattern::Spiral(arms) => {
+                    // sectors twisting by a whole turn from the center to the smallest side
+                    let phase = turn * arms as f32 + distance / cx.min(cy);
+                    (phase.fract() * color_number as f32) as u32 % color_number
+                }
+                Pattern::SingleSeed(radius) => {
+                    // the seed is centered on the center cell, even when the grid has an even size
+                    let (sx, sy) = (cell_number_x / 2, cell_number_y / 2);
+                    let (dx, dy) = (x.abs_diff(sx) as u64, y.abs_diff(sy) as u64);
+                    (dx * dx + dy * dy <= radius as u64 * radius as u64) as u32
+                }
+                Pattern::Quadrants => {
+                    let quadrant = match (x as f32 + 0.5 >= cx, y as f32 + 0.5 >= cy) {
+                        (true, true) => 0,
+                        (false, true) => 1,
+                        (false, false) => 2,
+                        (true, false) => 3,
+                    };
+                    quadrant % color_number
+                }
+                Pattern::Noise(density) => {
+                    if rng.generate::<f32>() < density {
+                        draw.weighted(Some(0), color_number, rng)
+                    } else {
+                        0
+                    }
+                }
+            }
+        };
+
+        (0..cell_number_y)
+            .flat_map(|y| (0..cell_number_x).map(move |x| (x, y)))
+            .map(|(x, y)| color_of(x, y, rng))
+            .collect()
+    }
+}
+
+/// How the colors of random cells are drawn
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColorDraw {
+    /// relative density of each color, uniform if none
+    pub weights: Option<Vec<f32>>,
+    /// color covering the grid but for this proportion of cells, of the other colors
+    pub background: Option<(u32, f32)>,
+}
+
+impl ColorDraw {
+    /// A random color, most likely the background one if any
+    pub fn color(&self, color_number: u32, rng: &mut WyRand) -> u32 {
+        match self.background {
+            Some((background, density)) => {
+                if rng.generate::<f32>() < density {
+                    self.weighted(Some(background), color_number, rng)
+                } else {
+                    background
+                }
+            }
+            None => self.weighted(None, color_number, rng),
+        }
+    }
+
+    /// A random color following the weights, other than `excluded`
+    fn weighted(&self, excluded: Option<u32>, color_number: u32, rng: &mut WyRand) -> u32 {
+        let Some(weights) = &self.weights else {
+            return match excluded {
+                Some(excluded) => {
+                    let color = rng.generate_range(0..color_number - 1);
+                    color + (color >= excluded) as u32
+                }
+                None => rng.generate_range(0..color_number),
+            };
+        };
+
+        let weight = |color: u32| {
+            if Some(color) == excluded {
+                0.0
+            } else {
+                weights[color as usize]
+            }
+        };
+        let total = (0..color_number).map(weight).sum::<f32>();
+        let mut left = rng.generate::<f32>() * total;
+        for color in 0..color_number {
+            left -= weight(color);
+            if left < 0.0 {
+                return color;
+            }
+        }
+        // rounding errors, or only the excluded color has a weight
+        (0..color_number)
+            .rev()
+            .find(|&color| weight(color) > 0.0)
+            .or(excluded)
+            .unwrap_or(0)
+    }
+}
+
+/// Where the colors of the cells seeded from an image come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImagePalette {
+    /// the colormap, random or loaded
+    Colormap,
+    /// the most representative colors of the image, replacing the colormap
+    Image,
+}
+
+impl FromStr for ImagePalette {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "colormap" => Ok(ImagePalette::Colormap),
+            "image" => Ok(ImagePalette::Image),
+            _ => Err("'Init palette' should be one of 'colormap' or 'image'"),
+        }
+    }
+}
+
+/// An image the initial cells are sampled from, as packed 8 bits sRGB pixels
+#[derive(Debug, Clone)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    rgb: Vec<u8>,
+}
+
+impl Image {
+    /// Reads a PNG or a binary PPM (P6) image, told apart by their content
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let image = if file.fill_buf()?.starts_with(b"P6") {
+            Self::read_ppm(file)?
+        } else {
+            Self::read_png(file)?
+        };
+        if image.width == 0 || image.height == 0 {
+            return Err(invalid("empty image"));
+        }
+        Ok(image)
+    }
+
+    fn read_png(file: impl Read) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(file);
+        // palettes and low bit depths to 8 bits per channel
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+
+        // alpha is dropped, gray is spread to the 3 channels
+        let rgb = match info.color_type {
+            png::ColorType::Rgb => buf,
+            png::ColorType::Rgba => buf
+                .chunks_exact(4)
+                .flat_map(|p| [p[0], p[1], p[2]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g; 3]).collect(),
+            png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map(|p| [p[0]; 3]).collect(),
+            png::ColorType::Indexed => return Err(invalid("unexpanded indexed PNG")),
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            rgb,
+        })
+    }
+
+    fn read_ppm(mut file: impl BufRead) -> io::Result<Self> {
+        // header: "P6", width, height and maxval separated by whitespaces and comments,
+        // then a single whitespace before the pixels
+        let mut fields = Vec::with_capacity(4);
+        let mut field = Vec::new();
+        while fields.len() < 4 {
+            let mut byte = [0];
+            file.read_exact(&mut byte)?;
+            match byte[0] {
+                b'#' if field.is_empty() => {
+                    file.read_until(b'\n', &mut Vec::new())?;
+                }
+                c if c.is_ascii_whitespace() => {
+                    if !field.is_empty() {
+                        fields.push(String::from_utf8_lossy(&field).into_owned());
+                        field.clear();
+                    }
+                }
+                c => field.push(c),
+            }
+        }
+        let [width, height, maxval] = [&fields[1], &fields[2], &fields[3]].map(|field| {
+            field
+                .parse::<u32>()
+                .map_err(|_| invalid("invalid PPM header"))
+        });
+        let (width, height, maxval) = (width?, height?, maxval?);
+        if !(1..=u16::MAX as u32).contains(&maxval) {
+            return Err(invalid("invalid PPM maxval"));
+        }
+
+        // the pixels read are compared to the size in the header rather than allocated from it
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let sample_size = if maxval < 256 { 1 } else { 2 };
+        let samples = width as u64 * height as u64 * 3;
+        if (bytes.len() as u64) < samples * sample_size {
+            return Err(invalid("truncated PPM, fewer pixels than its size"));
+        }
+        bytes.truncate((samples * sample_size) as usize);
+        // samples above maxval are as bright as it
+        let scale = |c: u32| (c.min(maxval) * 255 / maxval) as u8;
+        let rgb = if maxval < 256 {
+            bytes.iter().map(|&c| scale(c as u32)).collect()
+        } else {
+            // 2 bytes per sample, big endian
+            bytes
+                .chunks_exact(2)
+                .map(|c| scale(u16::from_be_bytes([c[0], c[1]]) as u32))
+                .collect()
+        };
+        Ok(Self { width, height, rgb })
+    }
+
+    /// Resamples the image to `cell_number_x` x `cell_number_y`, each cell averaging the pixels it covers
+    ///
+    /// Like on screen, the first row of cells is the bottom of the image
+    pub fn resample(&self, cell_number_x: u32, cell_number_y: u32) -> Vec<[u8; 3]> {
+        // pixels [start, end) covered by cell i out of n, at least one when upsampling
+        let span = |i: u32, n: u32, len: u32| {
+            let start = (i as u64 * len as u64 / n as u64) as u32;
+            let end = ((i as u64 + 1) * len as u64 / n as u64) as u32;
+            start..end.max(start + 1)
+        };
+
+        let mut colors = Vec::with_capacity(cell_number_x as usize * cell_number_y as usize);
+        for y in (0..cell_number_y).rev() {
+            let rows = span(y, cell_number_y, self.height);
+            for x in 0..cell_number_x {
+                let columns = span(x, cell_number_x, self.width);
+                let mut sum = [0_u64; 3];
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        let i = (row as usize * self.width as usize + column as usize) * 3;
+                        for (s, &c) in sum.iter_mut().zip(&self.rgb[i..i + 3]) {
+                            *s += c as u64;
+                        }
+                    }
+                }
+                let count = rows.len() as u64 * columns.len() as u64;
+                colors.push(sum.map(|s| ((s + count / 2) / count) as u8));
+            }
+        }
+        colors
+    }
+}
+
+/// Colors of the cells, the index of the nearest color of `palette` for each of `colors`
+pub fn quantize(colors: &[[u8; 3]], palette: &[[u8; 3]]) -> Vec<u32> {
+    let distance = |a: &[u8; 3], b: &[u8; 3]| {
+        a.iter()
+            .zip(b)
+            .map(|(&a, &b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    colors
+        .iter()
+        .map(|color| {
+            (0..palette.len())
+                .min_by_key(|&i| distance(color, &palette[i]))
+                .unwrap() as u32
+        })
+        .collect()
+}
+
+/// At most `color_number` colors representing `colors`, by median cut: the box of colors
+/// spanning the widest range on a channel is split in two at its median, until there are enough
+/// boxes or none can be split anymore
+pub fn median_cut(colors: &[[u8; 3]], color_number: u32) -> Vec<[u8; 3]> {
+    let range = |colors: &[[u8; 3]], channel: usize| {
+        let (min, max) = colors.iter().fold((u8::MAX, 0), |(min, max), c| {
+            (min.min(c[channel]), max.max(c[channel]))
+        });
+        max.saturating_sub(min)
+    };
+
+    let mut boxes = vec![colors.to_vec()];
+    while boxes.len() < color_number as usize {
+        let widest = (0..boxes.len())
+            .flat_map(|i| (0..3).map(move |channel| (i, channel)))
+            .max_by_key(|&(i, channel)| range(&boxes[i], channel))
+            .filter(|&(i, channel)| range(&boxes[i], channel) > 0);
+        let Some((i, channel)) = widest else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|c| c[channel]);
+        // equal colors stay in the same box, so split where the channel changes next to the median
+        let median = colors[colors.len() / 2][channel];
+        let mut split = colors.partition_point(|c| c[channel] < median);
+        if split == 0 {
+            split = colors.partition_point(|c| c[channel] <= median);
+        }
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .filter(|colors| !colors.is_empty())
+        .map(|colors| {
+            let count = colors.len() as u64;
+            let mut sum = [0_u64; 3];
+            for c in colors {
+                for (s, &c) in sum.iter_mut().zip(c) {
+                    *s += c as u64;
+                }
+            }
+            sum.map(|s| ((s + count / 2) / count) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ppm(header: &str, pixels: &[u8]) -> io::Result<Image> {
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(pixels);
+        Image::read_ppm(&bytes[..])
+    }
+
+    #[test]
+    fn parse_patterns() {
+        for (s, pattern) in [
+            ("random", Pattern::Random),
+            ("stripes", Pattern::Stripes(None)),
+            ("stripes:4", Pattern::Stripes(Some(4))),
+            ("rings:2", Pattern::Rings(Some(2))),
+            ("sectors", Pattern::Sectors(1)),
+            ("sectors:3", Pattern::Sectors(3)),
+            ("spiral:2", Pattern::Spiral(2)),
+            ("single-seed", Pattern::SingleSeed(0)),
+            ("single-seed:5", Pattern::SingleSeed(5)),
+            ("quadrants", Pattern::Quadrants),
+            ("noise:0.5", Pattern::Noise(0.5)),
+            ("noise:1", Pattern::Noise(1.0)),
+        ] {
+            assert_eq!(s.parse::<Pattern>(), Ok(pattern), "{s}");
+        }
+        for s in [
+            "",
+            "circles",
+            "random:3",
+            "stripes:0",
+            "rings:-1",
+            "sectors:x",
+            "quadrants:2",
+            "single-seed:-1",
+            "noise",
+            "noise:0",
+            "noise:1.5",
+        ] {
+            assert!(s.parse::<Pattern>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn patterns_deterministic_and_in_range() {
+        let draws = [
+            ColorDraw::default(),
+            ColorDraw {
+                weights: Some(vec![1.0, 0.0, 2.0]),
+                background: Some((2, 0.3)),
+            },
+        ];
+        for pattern in [
+            "random",
+            "stripes",
+            "stripes:2",
+            "rings",
+            "rings:3",
+            "sectors:2",
+            "spiral:3",
+            "single-seed:2",
+            "quadrants",
+            "noise:0.4",
+        ] {
+            let pattern = pattern.parse::<Pattern>().unwrap();
+            for draw in &draws {
+                let cells = |seed| pattern.cells(13, 8, 3, draw, &mut WyRand::new_seed(seed));
+                let first = cells(1);
+                assert_eq!(first.len(), 13 * 8);
+                assert_eq!(first, cells(1), "{pattern:?} isn't deterministic");
+                assert!(first.iter().all(|&color| color < 3), "{pattern:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn sectors_one_wedge_per_color() {
+        let cells = |pattern: Pattern| {
+            pattern.cells(20, 20, 4, &ColorDraw::default(), &mut WyRand::new_seed(0))
+        };
+        // a quarter turn per color, counterclockwise from the top right quadrant
+        let sectors = cells(Pattern::Sectors(1));
+        assert_eq!(sectors, cells(Pattern::Quadrants));
+        for color in 0..4 {
+            assert_eq!(sectors.iter().filter(|&&c| c == color).count(), 100);
+        }
+        // top right cell, then top left, bottom left and bottom right
+        assert_eq!(
+            [
+                sectors[19 * 20 + 19],
+                sectors[19 * 20],
+                sectors[0],
+                sectors[19]
+            ],
+            [0, 1, 2, 3]
+        );
+    }
+
+    /// Proportion of each color among `draws` colors drawn by `draw`
+    fn densities(draws: usize, mut draw: impl FnMut(&mut WyRand) -> u32) -> Vec<f32> {
+        let mut rng = WyRand::new_seed(7);
+        let mut counts = [0; 3];
+        for _ in 0..draws {
+            counts[draw(&mut rng) as usize] += 1;
+        }
+        counts
+            .iter()
+            .map(|&count| count as f32 / draws as f32)
+            .collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() < 0.01),
+            "{actual:?} instead of {expected:?}"
+        );
+    }
+
+    #[test]
+    fn color_weights_densities() {
+        let draw = ColorDraw {
+            weights: Some(vec![1.0, 0.0, 3.0]),
+            background: None,
+        };
+        assert_close(
+            &densities(100_000, |rng| draw.color(3, rng)),
+            &[0.25, 0.0, 0.75],
+        );
+        // uniform by default
+        let draw = ColorDraw::default();
+        assert_close(
+            &densities(100_000, |rng| draw.color(3, rng)),
+            &[1.0 / 3.0; 3],
+        );
+    }
+
+    #[test]
+    fn background_never_drawn_as_sparse() {
+        for weights in [None, Some(vec![1.0, 5.0, 3.0])] {
+            let draw = ColorDraw {
+                weights,
+                background: Some((1, 0.2)),
+            };
+            let sparse = densities(100_000, |rng| draw.weighted(Some(1), 3, rng));
+            assert_eq!(sparse[1], 0.0);
+            // the background weight aside, the others keep theirs
+            let expected = if draw.weights.is_some() {
+                [0.25, 0.0, 0.75]
+            } else {
+                [0.5, 0.0, 0.5]
+            };
+            assert_close(&sparse, &expected);
+            // and the sparse cells have the density of the background
+            assert_close(&densities(100_000, |rng| draw.color(3, rng))[1..2], &[0.8]);
+        }
+    }
+
+    #[test]
+    fn weighted_only_excluded_has_weight() {
+        // rejected by the arguments, but the excluded color is then the only one left
+        let draw = ColorDraw {
+            weights: Some(vec![0.0, 1.0, 0.0]),
+            background: None,
+        };
+        let mut rng = WyRand::new_seed(0);
+        assert!((0..100).all(|_| draw.weighted(Some(1), 3, &mut rng) == 1));
+    }
+
+    #[test]
+    fn read_ppm_with_comments() {
+        let image = ppm(
+            "P6\n# made by hand\n2 # width\n1\n255\n",
+            &[1, 2, 3, 4, 5, 6],
+        )
+        .unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.rgb, [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn read_ppm_16_bits() {
+        let pixels = [0xff, 0xff, 0x80, 0x00, 0x00, 0x00];
+        let image = ppm("P6 1 1 65535\n", &pixels).unwrap();
+        assert_eq!(image.rgb, [255, 127, 0]);
+    }
+
+    #[test]
+    fn read_ppm_scales_and_clamps_samples() {
+        let image = ppm("P6 1 1 100\n", &[50, 100, 200]).unwrap();
+        assert_eq!(image.rgb, [127, 255, 255]);
+        let image = ppm("P6 1 1 1000\n", &[0x01, 0xf4, 0x0f, 0xff, 0x03, 0xe8]).unwrap();
+        assert_eq!(image.rgb, [127, 255, 255]);
+    }
+
+    #[test]
+    fn read_ppm_truncated() {
+        for (header, pixels) in [
+            ("P6 2 2 255\n", &[0_u8; 11][..]),
+            ("P6 1 1 65535\n", &[0; 5][..]),
+            ("P6 60000 60000 255\n", &[0; 2][..]),
+        ] {
+            let err = ppm(header, pixels).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{header}");
+        }
+        // in the header
+        assert!(ppm("P6 2 2", &[]).is_err());
+        assert!(ppm("P6 2 x 255\n", &[0; 12]).is_err());
+    }
+
+    #[test]
+    fn resample_down() {
+        // 4x2, averaged by 2x2 blocks
+        let image = Image {
+            width: 4,
+            height: 2,
+            rgb: [[0, 0, 0], [10, 20, 30], [100, 100, 100], [200, 200, 200]]
+                .repeat(2)
+                .concat(),
+        };
+        assert_eq!(image.resample(2, 1), [[5, 10, 15], [150, 150, 150]]);
+        // the first row of cells is the bottom of the image
+        let image = Image {
+            width: 1,
+            height: 2,
+            rgb: vec![255, 0, 0, 0, 0, 255],
+        };
+        assert_eq!(image.resample(1, 2), [[0, 0, 255], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn resample_up() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            rgb: vec![1, 1, 1, 2, 2, 2],
+        };
+        assert_eq!(
+            image.resample(4, 2),
+            [[1, 1, 1], [1, 1, 1], [2, 2, 2], [2, 2, 2]].repeat(2)
+        );
+    }
+
+    #[test]
+    fn median_cut_fewer_colors() {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]].repeat(5);
+        let mut palette = median_cut(&colors, 8);
+        palette.sort();
+        assert_eq!(palette, [[0, 0, 255], [0, 255, 0], [255, 0, 0]]);
+        // a single color can't be split
+        assert_eq!(median_cut(&[[7, 8, 9]; 10], 4), [[7, 8, 9]]);
+    }
+
+    #[test]
+    fn median_cut_splits_widest() {
+        // dark and bright grays, the two boxes averaging each group
+        let colors = [[0, 0, 0], [10, 10, 10], [240, 240, 240], [250, 250, 250]];
+        let mut palette = median_cut(&colors, 2);
+        palette.sort();
+        assert_eq!(palette, [[5, 5, 5], [245, 245, 245]]);
+        assert_eq!(quantize(&colors, &palette), [0, 0, 1, 1]);
+    }
+}
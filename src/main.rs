@@ -1,5 +1,13 @@
 mod app;
+mod export;
 mod graphics;
+mod headless;
+mod init;
+mod population;
+mod record;
+mod rule;
+mod simulation;
+mod snapshot;
 mod window;
 
 use app::{AppArgs, AppState};
@@ -9,21 +17,27 @@ use winit::event_loop::EventLoop;
 
 pub fn main() {
     let app_args = AppArgs::parse().expect("Failed to parse args");
+    let (width, height) = (app_args.width, app_args.height);
+    let headless_args = app_args.headless.clone();
+
+    let state = AppState::new(app_args);
+    // so that interesting runs can be reproduced
+    eprintln!("Seed: {}", state.seed);
+
+    if let Some(headless_args) = headless_args {
+        env_logger::builder().format_timestamp_nanos().init();
+        pollster::block_on(headless::run(state, headless_args));
+        return;
+    }
 
     let event_loop = EventLoop::new().unwrap();
     #[allow(unused_mut)]
     let mut builder = winit::window::WindowBuilder::new()
         .with_title("SAXRUMFEX")
-        .with_inner_size(winit::dpi::LogicalSize::new(
-            app_args.window_size,
-            app_args.window_size,
-        ))
-        .with_resizable(false)
+        .with_inner_size(winit::dpi::LogicalSize::new(width, height))
         .with_active(true);
     // .with_window_icon(window_icon);
 
-    let state = AppState::new(app_args);
-
     let window = builder.build(&event_loop).unwrap();
     let window = Arc::new(window);
 
@@ -0,0 +1,512 @@
+//! Number of cells of each color at every generation, counted on the gpu right after each step
+//!
+//! The counts of a batch of generations are copied to a staging buffer read back asynchronously,
+//! so they come a frame or two later but never stall the rendering. Once read, the buffers of
+//! a batch are reused by the next ones.
+
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::simulation::{work_group_count, SIZE_OF_U32};
+
+/// must match the '@workgroup_size' of the population shader
+const CELLS_PER_GROUP: u32 = 256;
+
+/// Number of cells of each color at a generation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Population {
+    pub generation: usize,
+    pub counts: Vec<u32>,
+}
+
+impl Population {
+    /// Counts `cells` on the cpu
+    pub fn count(generation: usize, cells: &[u32], color_number: u32) -> Self {
+        let mut counts = vec![0; color_number as usize];
+        for &color in cells {
+            counts[color as usize] += 1;
+        }
+        Self { generation, counts }
+    }
+}
+
+/// Buffers where the counts of up to `capacity` generations are written, then read back from
+struct CountBuffers {
+    counts_buffer: wgpu::Buffer,
+    staging_buffer: wgpu::Buffer,
+    /// one per cells buffer, as for the compute shader
+    bind_groups: Vec<wgpu::BindGroup>,
+    capacity: u32,
+}
+
+/// Counts of a batch of generations, on their way back to the cpu
+struct Readback {
+    first_generation: usize,
+    generations: u32,
+    buffers: CountBuffers,
+    /// set once the staging buffer is mapped, none until its mapping is requested
+    mapped: Option<Arc<AtomicBool>>,
+}
+
+/// Counts the cells of each color with a compute shader, one dispatch after each step
+pub struct PopulationCounter {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    /// workgroups dispatched in x and y
+    work_group_count: (u32, u32),
+
+    color_number: u32,
+    /// bytes between the counts of two generations, aligned for dynamic offsets
+    stride: u64,
+
+    /// oldest first
+    readbacks: VecDeque<Readback>,
+    /// buffers of the batches read back, to be reused
+    free_buffers: Vec<CountBuffers>,
+}
+
+/// Buffers where the counts of a batch of generations are written
+pub struct CountBatch {
+    buffers: CountBuffers,
+    generations: u32,
+}
+
+impl PopulationCounter {
+    /// Counter of the `color_number` colors of grids of `cell_number` cells
+    pub fn new(
+        device: &wgpu::Device,
+        sim_param_buffer: &wgpu::Buffer,
+        cell_number: u32,
+        color_number: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Population shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                "./shaders/population.wgsl"
+            ))),
+        });
+
+        let counts_size = color_number as u64 * SIZE_OF_U32;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(sim_param_buffer.size()),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new((cell_number as u64) * SIZE_OF_U32),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        // the counts of each generation of a batch are at their own offset
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(counts_size),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Population bind group layout"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("population pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Population pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        let alignment = device.limits().min_storage_buffer_offset_alignment as u64;
+        Self {
+            bind_group_layout,
+            pipeline,
+            work_group_count: work_group_count(device, cell_number, CELLS_PER_GROUP),
+
+            color_number,
+            stride: counts_size.div_ceil(alignment) * alignment,
+
+            readbacks: VecDeque::new(),
+            free_buffers: vec![],
+        }
+    }
+
+    /// Where the counts of the next `generations` generations will be written,
+    /// zeroed by `command_encoder` as the shader expects
+    pub fn batch(
+        &mut self,
+        device: &wgpu::Device,
+        command_encoder: &mut wgpu::CommandEncoder,
+        sim_param_buffer: &wgpu::Buffer,
+        cells_buffers: &[wgpu::Buffer],
+        generations: u32,
+    ) -> CountBatch {
+        let buffers = match self
+            .free_buffers
+            .iter()
+            .position(|buffers| buffers.capacity >= generations)
+        {
+            Some(i) => self.free_buffers.swap_remove(i),
+            // rounded up so that batches of about the same size share their buffers
+            None => self.create_buffers(
+                device,
+                sim_param_buffer,
+                cells_buffers,
+                generations.next_power_of_two(),
+            ),
+        };
+        command_encoder.clear_buffer(
+            &buffers.counts_buffer,
+            0,
+            Some(self.stride * generations as u64),
+        );
+
+        CountBatch {
+            buffers,
+            generations,
+        }
+    }
+
+    /// Buffers for the counts of `capacity` generations
+    fn create_buffers(
+        &self,
+        device: &wgpu::Device,
+        sim_param_buffer: &wgpu::Buffer,
+        cells_buffers: &[wgpu::Buffer],
+        capacity: u32,
+    ) -> CountBuffers {
+        let size = self.stride * capacity as u64;
+        let counts_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Population Buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Population Staging Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_groups = cells_buffers
+            .iter()
+            .enumerate()
+            .map(|(i, cells_buffer)| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: sim_param_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: cells_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                buffer: &counts_buffer,
+                                offset: 0,
+                                size: wgpu::BufferSize::new(self.color_number as u64 * SIZE_OF_U32),
+                            }),
+                        },
+                    ],
+                    label: Some(&format!("population bind group {i}")),
+                })
+            })
+            .collect();
+
+        CountBuffers {
+            counts_buffer,
+            staging_buffer,
+            bind_groups,
+            capacity,
+        }
+    }
+
+    /// Record the count of the `step`-th generation of `batch`, whose cells are in `cells_buffers[cells]`
+    pub fn dispatch<'a>(
+        &'a self,
+        cpass: &mut wgpu::ComputePass<'a>,
+        batch: &'a CountBatch,
+        step: u32,
+        cells: usize,
+    ) {
+        cpass.set_pipeline(&self.pipeline);
+        let offset = (self.stride * step as u64) as u32;
+        cpass.set_bind_group(0, &batch.buffers.bind_groups[cells], &[offset]);
+        cpass.dispatch_workgroups(self.work_group_count.0, self.work_group_count.1, 1);
+    }
+
+    /// Record the copy of the counts of `batch`, the generations `first_generation` and up,
+    /// to where they're read back from
+    pub fn finish_batch(
+        &mut self,
+        command_encoder: &mut wgpu::CommandEncoder,
+        batch: CountBatch,
+        first_generation: usize,
+    ) {
+        command_encoder.copy_buffer_to_buffer(
+            &batch.buffers.counts_buffer,
+            0,
+            &batch.buffers.staging_buffer,
+            0,
+            self.stride * batch.generations as u64,
+        );
+        self.readbacks.push_back(Readback {
+            first_generation,
+            generations: batch.generations,
+            buffers: batch.buffers,
+            mapped: None,
+        });
+    }
+
+    /// Counts read back so far, oldest first, waiting for all of them if `wait`
+    ///
+    /// Must only be called once the batches are submitted, their readback starting then
+    pub fn take(&mut self, device: &wgpu::Device, wait: bool) -> Vec<Population> {
+        for readback in &mut self.readbacks {
+            readback.mapped.get_or_insert_with(|| {
+                let mapped = Arc::new(AtomicBool::new(false));
+                let on_mapped = mapped.clone();
+                readback
+                    .buffers
+                    .staging_buffer
+                    .slice(..self.stride * readback.generations as u64)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        result.expect("Failed to map the population staging buffer");
+                        on_mapped.store(true, Ordering::Release);
+                    });
+                mapped
+            });
+        }
+        device.poll(if wait {
+            wgpu::Maintain::Wait
+        } else {
+            wgpu::Maintain::Poll
+        });
+
+        // batches are submitted in order, so mapped in order
+        let mut populations = vec![];
+        while let Some(readback) = self
+            .readbacks
+            .front()
+            .filter(|r| r.mapped.as_ref().is_some_and(|m| m.load(Ordering::Acquire)))
+        {
+            {
+                let data = readback
+                    .buffers
+                    .staging_buffer
+                    .slice(..self.stride * readback.generations as u64)
+                    .get_mapped_range();
+                for step in 0..readback.generations as usize {
+                    let start = step * self.stride as usize;
+                    let counts = &data[start..start + self.color_number as usize * 4];
+                    populations.push(Population {
+                        generation: readback.first_generation + step,
+                        counts: bytemuck::cast_slice(counts).to_vec(),
+                    });
+                }
+            }
+            readback.buffers.staging_buffer.unmap();
+            let readback = self.readbacks.pop_front().unwrap();
+            self.free_buffers.push(readback.buffers);
+        }
+
+        populations
+    }
+}
+
+/// Populations written as CSV, a line per generation
+pub struct PopulationLog {
+    file: BufWriter<File>,
+}
+
+impl PopulationLog {
+    /// Creates the file and writes its header
+    pub fn new(path: &Path, color_number: u32) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "generation")?;
+        for color in 0..color_number {
+            write!(file, ",color_{color}")?;
+        }
+        writeln!(file)?;
+        Ok(Self { file })
+    }
+
+    pub fn write(&mut self, populations: &[Population]) -> io::Result<()> {
+        for population in populations {
+            write!(self.file, "{}", population.generation)?;
+            for count in &population.counts {
+                write!(self.file, ",{count}")?;
+            }
+            writeln!(self.file)?;
+        }
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nanorand::{Rng, WyRand};
+    use wgpu::util::DeviceExt;
+
+    /// Any device able to run compute shaders, the fallback adapter included
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::default();
+        let adapter = [false, true]
+            .into_iter()
+            .find_map(|force_fallback_adapter| {
+                pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                }))
+            })?;
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return None;
+        }
+        pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::downlevel_defaults(),
+            },
+            None,
+        ))
+        .ok()
+    }
+
+    /// Counts random grids on the gpu in batches of several generations, checked against the cpu
+    fn assert_counts(color_number: u32) {
+        let Some((device, queue)) = device() else {
+            eprintln!("no adapter able to run compute shaders, skipping");
+            return;
+        };
+
+        // a few workgroups, the last one partial
+        let (width, height) = (37, 29);
+        let cell_number = width * height;
+        // only the cell and color numbers are read by the population shader
+        let sim_params = [
+            width,
+            height,
+            cell_number,
+            color_number,
+            1,
+            u32::MAX,
+            8,
+            0,
+            0,
+        ];
+        let sim_param_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&sim_params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let cells_buffers = [0, 1].map(|_| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: cell_number as u64 * SIZE_OF_U32,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        let mut counter =
+            PopulationCounter::new(&device, &sim_param_buffer, cell_number, color_number);
+
+        let mut rng = WyRand::new_seed(color_number as u64);
+        let (mut expected, mut populations) = (vec![], vec![]);
+        let mut generation = 0;
+        // the second batch reuses the buffers of the first one, the third one needs bigger ones
+        for generations in [3, 2, 5] {
+            // the generations of a batch alternate between the two cells buffers
+            let grids = [0, 1].map(|_| {
+                (0..cell_number)
+                    .map(|_| rng.generate_range(0..color_number))
+                    .collect::<Vec<_>>()
+            });
+            for (buffer, grid) in cells_buffers.iter().zip(&grids) {
+                queue.write_buffer(buffer, 0, bytemuck::cast_slice(grid));
+            }
+
+            let mut encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            let batch = counter.batch(
+                &device,
+                &mut encoder,
+                &sim_param_buffer,
+                &cells_buffers,
+                generations,
+            );
+            {
+                let mut cpass = encoder.begin_compute_pass(&Default::default());
+                for step in 0..generations {
+                    counter.dispatch(&mut cpass, &batch, step, step as usize % 2);
+                }
+            }
+            counter.finish_batch(&mut encoder, batch, generation);
+            queue.submit(Some(encoder.finish()));
+
+            for step in 0..generations as usize {
+                expected.push(Population::count(
+                    generation + step,
+                    &grids[step % 2],
+                    color_number,
+                ));
+            }
+            generation += generations as usize;
+            // read back before the next batch, for its buffers to be reused
+            populations.extend(counter.take(&device, true));
+        }
+        assert_eq!(populations, expected, "{color_number} colors");
+    }
+
+    #[test]
+    fn shared_counts() {
+        assert_counts(3);
+        assert_counts(256);
+    }
+
+    #[test]
+    fn global_counts() {
+        assert_counts(257);
+        assert_counts(4096);
+    }
+}
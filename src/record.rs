@@ -0,0 +1,222 @@
+//! Recording of generations as an animation, one frame every few generations
+//!
+//! Cells are already indices into the colormap, so as long as there are at most 256 colors
+//! the frames are stored as indexed-color images with the colormap as their palette.
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{app::AppState, export::colormap_to_rgb8};
+
+/// time between two frames of the animation, in hundredths of a second
+const FRAME_DELAY: u16 = 4;
+
+/// the most colors an indexed-color image can have
+const MAX_PALETTE_COLORS: u32 = 256;
+
+/// Animation formats, chosen from the extension of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Gif,
+    Apng,
+}
+
+impl RecordFormat {
+    /// 'gif' for GIF, 'png' or 'apng' for APNG
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match ext.as_deref() {
+            Some("gif") => Ok(RecordFormat::Gif),
+            Some("png" | "apng") => Ok(RecordFormat::Apng),
+            _ => Err(format!(
+                "{} should end with '.gif', '.png' or '.apng' to be recorded",
+                path.display()
+            )),
+        }
+    }
+
+    /// Whether animations with `colors` colors can be written in this format
+    pub fn supports_colors(self, colors: u32) -> bool {
+        // GIF is always indexed, APNG falls back to RGB
+        self == RecordFormat::Apng || colors <= MAX_PALETTE_COLORS
+    }
+}
+
+/// Frames being recorded, written as they come unless the number of frames in the APNG header
+/// isn't known until the end
+enum Sink {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(png::Writer<BufWriter<File>>),
+    /// frames of an APNG recording that may stop early, kept until then
+    BufferedApng(Vec<Vec<u8>>),
+}
+
+/// Records `frames` generations, one every `every` generations
+pub struct Recorder {
+    pub path: PathBuf,
+    sink: Sink,
+
+    width: u32,
+    height: u32,
+    /// 8 bits sRGB colormap, as the palette or to convert the cells to RGB
+    palette: Vec<[u8; 3]>,
+
+    every: u32,
+    frames_left: u32,
+    /// generation of the next frame
+    next_generation: usize,
+}
+
+impl Recorder {
+    /// Start recording at `generation`, the first frame being that generation
+    ///
+    /// If the recording `may_stop_early`, before all its frames are recorded, APNG frames are
+    /// kept in memory until it's finished
+    pub fn new(
+        path: PathBuf,
+        state: &AppState,
+        generation: usize,
+        frames: u32,
+        every: u32,
+        may_stop_early: bool,
+    ) -> io::Result<Self> {
+        let format = RecordFormat::from_path(&path).map_err(io::Error::other)?;
+        if !format.supports_colors(state.color_number) {
+            return Err(io::Error::other(format!(
+                "GIF can't have more than {MAX_PALETTE_COLORS} colors, record as APNG instead"
+            )));
+        }
+
+        let palette = colormap_to_rgb8(&state.colormap);
+        let sink = match format {
+            RecordFormat::Gif => {
+                let (Ok(width), Ok(height)) = (
+                    u16::try_from(state.cell_number_x),
+                    u16::try_from(state.cell_number_y),
+                ) else {
+                    return Err(io::Error::other("the grid is too big for a GIF"));
+                };
+                let mut encoder = gif::Encoder::new(
+                    BufWriter::new(File::create(&path)?),
+                    width,
+                    height,
+                    palette.as_flattened(),
+                )
+                .map_err(io::Error::other)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(io::Error::other)?;
+                Sink::Gif(encoder)
+            }
+            RecordFormat::Apng if may_stop_early => Sink::BufferedApng(vec![]),
+            RecordFormat::Apng => {
+                let file = BufWriter::new(File::create(&path)?);
+                let mut encoder =
+                    apng_encoder(file, state.cell_number_x, state.cell_number_y, &palette);
+                encoder.set_animated(frames, 0)?;
+                encoder.set_frame_delay(FRAME_DELAY, 100)?;
+                Sink::Apng(encoder.write_header()?)
+            }
+        };
+
+        Ok(Self {
+            path,
+            sink,
+
+            width: state.cell_number_x,
+            height: state.cell_number_y,
+            palette,
+
+            every,
+            frames_left: frames,
+            next_generation: generation,
+        })
+    }
+
+    /// Generation of the next frame
+    pub fn next_generation(&self) -> usize {
+        self.next_generation
+    }
+
+    /// Add `cells` (of the generation returned by `next_generation`) as the next frame,
+    /// returns whether all the frames have been recorded
+    pub fn record(&mut self, cells: &[u32]) -> io::Result<bool> {
+        // like on screen, the first row of cells is at the bottom
+        let rows = cells.chunks_exact(self.width as usize).rev();
+        let frame = if self.palette.len() <= MAX_PALETTE_COLORS as usize {
+            rows.flatten().map(|&color| color as u8).collect::<Vec<_>>()
+        } else {
+            rows.flatten()
+                .flat_map(|&color| self.palette[color as usize])
+                .collect()
+        };
+
+        match &mut self.sink {
+            Sink::Gif(encoder) => encoder
+                .write_frame(&gif::Frame {
+                    width: self.width as u16,
+                    height: self.height as u16,
+                    delay: FRAME_DELAY,
+                    buffer: Cow::Owned(frame),
+                    ..gif::Frame::default()
+                })
+                .map_err(io::Error::other)?,
+            Sink::Apng(writer) => writer.write_image_data(&frame)?,
+            Sink::BufferedApng(frames) => frames.push(frame),
+        }
+
+        self.frames_left = self.frames_left.saturating_sub(1);
+        self.next_generation += self.every as usize;
+        Ok(self.frames_left == 0)
+    }
+
+    /// Write the end of the animation, even if not all the frames have been recorded
+    pub fn finish(self) -> io::Result<()> {
+        match self.sink {
+            // writes the trailer
+            Sink::Gif(encoder) => encoder.into_inner().map_err(io::Error::other)?.flush(),
+            // fails if not all the frames announced in the header have been written
+            Sink::Apng(writer) => Ok(writer.finish()?),
+            Sink::BufferedApng(frames) if frames.is_empty() => {
+                Err(io::Error::other("no frame has been recorded"))
+            }
+            Sink::BufferedApng(frames) => {
+                let file = BufWriter::new(File::create(&self.path)?);
+                let mut encoder = apng_encoder(file, self.width, self.height, &self.palette);
+                encoder.set_animated(frames.len() as u32, 0)?;
+                encoder.set_frame_delay(FRAME_DELAY, 100)?;
+
+                let mut writer = encoder.write_header()?;
+                for frame in &frames {
+                    writer.write_image_data(frame)?;
+                }
+                writer.finish()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Encoder of `width` x `height` frames, indexed if `palette` fits
+fn apng_encoder(
+    file: BufWriter<File>,
+    width: u32,
+    height: u32,
+    palette: &[[u8; 3]],
+) -> png::Encoder<'static, BufWriter<File>> {
+    let mut encoder = png::Encoder::new(file, width, height);
+    if palette.len() <= MAX_PALETTE_COLORS as usize {
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_palette(palette.as_flattened().to_vec());
+    } else {
+        encoder.set_color(png::ColorType::Rgb);
+    }
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+}
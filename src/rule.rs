@@ -0,0 +1,369 @@
+//! Cpu reference implementation of the cyclic automaton, the same rule as the one run by 'compute.wgsl'
+//!
+//! It's slower than the gpu, but it's the ground truth of what the shader should compute,
+//! and it's also used as a fallback when the adapter can't run compute shaders.
+
+use std::{ops::RangeInclusive, str::FromStr};
+
+/// largest radius of a neighborhood, a Moore one then having about 4 million neighbors
+pub const MAX_RADIUS: u32 = 1000;
+
+/// Parameters of the cyclic rule
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// number of colors, each color being the enemy of the previous one
+    pub colors: u32,
+    /// a cell is taken over when its number of enemies is within this range
+    pub threshold: RangeInclusive<u32>,
+    /// offsets (x, y) from a cell to each of its neighbors
+    pub neighborhood: Vec<(i32, i32)>,
+    /// what the neighbors outside of the grid are
+    pub boundary: Boundary,
+}
+
+/// How the neighbors outside of the grid are handled
+#[derive(Debug, Clone, Copy)]
+pub enum Boundary {
+    /// they're ignored
+    Clip,
+    /// the grid is a torus, they're taken from the opposite side
+    Wrap,
+    /// the grid is mirrored on its edges (without repeating the edge cells)
+    Reflect,
+    /// they all have the given color
+    Fixed(u32),
+}
+
+impl Boundary {
+    /// (mode, color) as understood by the compute shader
+    pub fn as_params(self) -> [u32; 2] {
+        match self {
+            Boundary::Clip => [0, 0],
+            Boundary::Wrap => [1, 0],
+            Boundary::Reflect => [2, 0],
+            Boundary::Fixed(color) => [3, color],
+        }
+    }
+
+    /// Inverse of `as_params`, none if the mode is unknown
+    pub fn from_params([mode, color]: [u32; 2]) -> Option<Self> {
+        match mode {
+            0 => Some(Boundary::Clip),
+            1 => Some(Boundary::Wrap),
+            2 => Some(Boundary::Reflect),
+            3 => Some(Boundary::Fixed(color)),
+            _ => None,
+        }
+    }
+
+    /// Brings back coordinate `c` of an axis of length `len` into the grid,
+    /// `None` if it stays outside of the grid
+    fn map_coordinate(self, c: i32, len: i32) -> Option<i32> {
+        if (0..len).contains(&c) {
+            return Some(c);
+        }
+        match self {
+            Boundary::Clip | Boundary::Fixed(_) => None,
+            Boundary::Wrap => Some(c.rem_euclid(len)),
+            Boundary::Reflect if len == 1 => Some(0),
+            Boundary::Reflect => {
+                // reflections repeat every 2*(len-1) cells
+                let period = 2 * (len - 1);
+                let c = c.rem_euclid(period);
+                Some(if c >= len { period - c } else { c })
+            }
+        }
+    }
+}
+
+impl FromStr for Boundary {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: &str =
+            "'Boundary' should be one of 'clip', 'wrap', 'reflect' or 'fixed:<color>'";
+        match s {
+            "clip" => Ok(Boundary::Clip),
+            "wrap" => Ok(Boundary::Wrap),
+            "reflect" => Ok(Boundary::Reflect),
+            _ => match s.strip_prefix("fixed:").map(str::parse::<u32>) {
+                Some(Ok(color)) => Ok(Boundary::Fixed(color)),
+                _ => Err(ERROR),
+            },
+        }
+    }
+}
+
+/// Shape of the neighborhood of a cell
+#[derive(Debug, Clone, Copy)]
+pub enum Neighborhood {
+    /// the (2r+1)x(2r+1) square around the cell
+    Moore,
+    /// the diamond of cells at a manhattan distance of at most r
+    VonNeumann,
+    /// the disk of cells at an euclidean distance of at most r
+    Circular,
+}
+
+impl Neighborhood {
+    /// Offsets (x, y) of the neighbors of a cell within `radius`, the cell itself being excluded
+    ///
+    /// `radius` must be at most `MAX_RADIUS`
+    pub fn offsets(self, radius: u32) -> Vec<(i32, i32)> {
+        let r = radius as i32;
+        (-r..=r)
+            .flat_map(|yoff| (-r..=r).map(move |xoff| (xoff, yoff)))
+            .filter(|&offset| offset != (0, 0))
+            .filter(|&(xoff, yoff)| match self {
+                Neighborhood::Moore => true,
+                Neighborhood::VonNeumann => xoff.abs() + yoff.abs() <= r,
+                Neighborhood::Circular => xoff * xoff + yoff * yoff <= r * r,
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Neighborhood {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "moore" => Ok(Neighborhood::Moore),
+            "von-neumann" => Ok(Neighborhood::VonNeumann),
+            "circular" => Ok(Neighborhood::Circular),
+            _ => Err("'Neighborhood' should be one of 'moore', 'von-neumann' or 'circular'"),
+        }
+    }
+}
+
+/// Parses a kernel mask into neighbor offsets
+///
+/// The mask is a grid of '#' or '1' (neighbor) and '.' or '0' (not a neighbor), with an odd
+/// number of rows and columns, the cell itself being at the center (and never its own neighbor).
+/// Like on screen, the first row is the top one.
+pub fn parse_kernel(mask: &str) -> Result<Vec<(i32, i32)>, String> {
+    let rows = mask
+        .lines()
+        .map(str::trim)
+        .filter(|row| !row.is_empty())
+        .collect::<Vec<_>>();
+
+    let height = rows.len();
+    let width = rows.first().map_or(0, |row| row.chars().count());
+    if height % 2 == 0 || width % 2 == 0 || rows.iter().any(|row| row.chars().count() != width) {
+        return Err(
+            "the kernel mask should be a grid with an odd number of rows and columns".into(),
+        );
+    }
+
+    let (cx, cy) = ((width / 2) as i32, (height / 2) as i32);
+    let mut offsets = vec![];
+    for (row_index, row) in rows.iter().enumerate() {
+        for (col_index, c) in row.chars().enumerate() {
+            let offset = (col_index as i32 - cx, cy - row_index as i32);
+            match c {
+                '#' | '1' if offset != (0, 0) => offsets.push(offset),
+                '#' | '1' | '.' | '0' => {}
+                _ => return Err(format!("unexpected character '{c}' in the kernel mask")),
+            }
+        }
+    }
+
+    if offsets.is_empty() {
+        return Err("the kernel mask should contain at least one neighbor".into());
+    }
+    Ok(offsets)
+}
+
+/// Computes the next generation of `cells`, a `width` x `height` grid stored row by row
+/// where each cell holds a color in `0..rule.colors`.
+///
+/// A cell is taken over by its enemy (the next color, cyclically) when the number of enemies
+/// among its neighbors (`rule.neighborhood`, out of bound ones being handled according to
+/// `rule.boundary`) is within `rule.threshold`.
+pub fn step(cells: &[u32], width: u32, height: u32, rule: &Rule) -> Vec<u32> {
+    assert_eq!(cells.len(), (width * height) as usize);
+
+    let (width, height) = (width as i32, height as i32);
+    let mut next = Vec::with_capacity(cells.len());
+    for y in 0..height {
+        for x in 0..width {
+            let color = cells[(y * width + x) as usize];
+            let enemy_color = enemy_of(color, rule.colors);
+
+            let mut enemies = 0;
+            for &(xoff, yoff) in &rule.neighborhood {
+                // neighbor position, brought back into the grid if out of bound
+                let xnei = rule.boundary.map_coordinate(x + xoff, width);
+                let ynei = rule.boundary.map_coordinate(y + yoff, height);
+                let neighbor_color = match (xnei, ynei, rule.boundary) {
+                    (Some(xnei), Some(ynei), _) => cells[(ynei * width + xnei) as usize],
+                    (_, _, Boundary::Fixed(color)) => color,
+                    _ => continue,
+                };
+
+                if neighbor_color == enemy_color {
+                    enemies += 1;
+                }
+            }
+
+            next.push(if rule.threshold.contains(&enemies) {
+                enemy_color
+            } else {
+                color
+            });
+        }
+    }
+    next
+}
+
+/// The color that can take over `color`
+fn enemy_of(color: u32, colors: u32) -> u32 {
+    (color + 1) % colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3 colors, 0 -> 1 -> 2 -> 0
+    fn rule(
+        boundary: Boundary,
+        neighborhood: &[(i32, i32)],
+        threshold: RangeInclusive<u32>,
+    ) -> Rule {
+        Rule {
+            colors: 3,
+            threshold,
+            neighborhood: neighborhood.to_vec(),
+            boundary,
+        }
+    }
+
+    const LEFT_RIGHT: &[(i32, i32)] = &[(-1, 0), (1, 0)];
+
+    #[test]
+    fn neighborhood_sizes() {
+        assert_eq!(Neighborhood::Moore.offsets(1).len(), 8);
+        assert_eq!(Neighborhood::VonNeumann.offsets(2).len(), 12);
+        assert_eq!(Neighborhood::Circular.offsets(2).len(), 12);
+        // the largest radius fits without overflowing
+        let r = MAX_RADIUS as usize;
+        assert_eq!(
+            Neighborhood::Moore.offsets(MAX_RADIUS).len(),
+            (2 * r + 1).pow(2) - 1
+        );
+        assert!(Neighborhood::Circular
+            .offsets(MAX_RADIUS)
+            .contains(&(0, -(r as i32))));
+    }
+
+    #[test]
+    fn map_coordinate_clip_and_fixed() {
+        for boundary in [Boundary::Clip, Boundary::Fixed(1)] {
+            assert_eq!(boundary.map_coordinate(2, 5), Some(2));
+            assert_eq!(boundary.map_coordinate(-1, 5), None);
+            assert_eq!(boundary.map_coordinate(5, 5), None);
+            assert_eq!(boundary.map_coordinate(-100, 5), None);
+        }
+    }
+
+    #[test]
+    fn map_coordinate_wrap() {
+        let wrap = |c| Boundary::Wrap.map_coordinate(c, 5);
+        assert_eq!(wrap(0), Some(0));
+        assert_eq!(wrap(-1), Some(4));
+        assert_eq!(wrap(-5), Some(0));
+        assert_eq!(wrap(-6), Some(4));
+        assert_eq!(wrap(-13), Some(2));
+        assert_eq!(wrap(5), Some(0));
+        assert_eq!(wrap(12), Some(2));
+    }
+
+    #[test]
+    fn map_coordinate_reflect() {
+        // 0 1 2 3 4 3 2 1 0 1 ..., without repeating the edge cells
+        let reflect = |c| Boundary::Reflect.map_coordinate(c, 5);
+        assert_eq!(reflect(-1), Some(1));
+        assert_eq!(reflect(-4), Some(4));
+        assert_eq!(reflect(-5), Some(3));
+        assert_eq!(reflect(-100), Some(4));
+        assert_eq!(reflect(5), Some(3));
+        assert_eq!(reflect(7), Some(1));
+        assert_eq!(reflect(8), Some(0));
+        assert_eq!(reflect(13), Some(3));
+        // a single cell is its own reflection
+        assert_eq!(Boundary::Reflect.map_coordinate(-7, 1), Some(0));
+        assert_eq!(Boundary::Reflect.map_coordinate(9, 1), Some(0));
+    }
+
+    #[test]
+    fn step_clip() {
+        let rule = rule(Boundary::Clip, LEFT_RIGHT, 1..=u32::MAX);
+        // the last cell has no right neighbor to be taken over by
+        assert_eq!(step(&[0, 1, 2], 3, 1, &rule), [1, 2, 2]);
+    }
+
+    #[test]
+    fn step_wrap() {
+        let rule = rule(Boundary::Wrap, LEFT_RIGHT, 1..=u32::MAX);
+        assert_eq!(step(&[0, 1, 2], 3, 1, &rule), [1, 2, 0]);
+
+        // far out neighbor, 7 cells right is 1 cell right
+        let rule = self::rule(Boundary::Wrap, &[(7, 0)], 1..=u32::MAX);
+        assert_eq!(step(&[0, 1, 2], 3, 1, &rule), [1, 2, 0]);
+        // same vertically, 7 cells up is 1 cell up and 7 cells down is 1 cell down
+        let rule = self::rule(Boundary::Wrap, &[(0, 7)], 1..=u32::MAX);
+        assert_eq!(step(&[0, 1, 2], 1, 3, &rule), [1, 2, 0]);
+        let rule = self::rule(Boundary::Wrap, &[(0, -7)], 1..=u32::MAX);
+        assert_eq!(step(&[0, 1, 2], 1, 3, &rule), [0, 1, 2]);
+    }
+
+    #[test]
+    fn step_reflect() {
+        // the first cell sees the second one on both sides
+        let rule = rule(Boundary::Reflect, LEFT_RIGHT, 2..=2);
+        assert_eq!(step(&[0, 1, 2], 3, 1, &rule), [1, 1, 2]);
+
+        // far out neighbor, 7 cells left: x=0 -> 1, x=1 -> 2, x=2 -> 1
+        let rule = self::rule(Boundary::Reflect, &[(-7, 0)], 1..=u32::MAX);
+        assert_eq!(step(&[0, 1, 2], 3, 1, &rule), [1, 2, 2]);
+    }
+
+    #[test]
+    fn step_fixed() {
+        let rule = rule(Boundary::Fixed(0), LEFT_RIGHT, 1..=u32::MAX);
+        assert_eq!(step(&[0, 1, 2], 3, 1, &rule), [1, 2, 0]);
+
+        // both sides of the first cell are enemies
+        let rule = self::rule(Boundary::Fixed(1), LEFT_RIGHT, 2..=2);
+        assert_eq!(step(&[0, 1, 2], 3, 1, &rule), [1, 1, 2]);
+    }
+
+    #[test]
+    fn step_threshold_range() {
+        // checkerboard on a 2x2 torus, every cell has 4 enemies among its 8 moore neighbors
+        let moore = Neighborhood::Moore.offsets(1);
+        let cells = [0, 1, 1, 0];
+        let taken_over = [1, 0, 0, 1];
+        for (threshold, expected) in [
+            (4..=u32::MAX, taken_over),
+            (1..=4, taken_over),
+            (4..=4, taken_over),
+            (5..=u32::MAX, cells),
+            (1..=3, cells),
+        ] {
+            let rule = Rule {
+                colors: 2,
+                threshold: threshold.clone(),
+                neighborhood: moore.clone(),
+                boundary: Boundary::Wrap,
+            };
+            assert_eq!(
+                step(&cells, 2, 2, &rule),
+                expected,
+                "threshold {threshold:?}"
+            );
+        }
+    }
+}
@@ -1,26 +1,47 @@
 struct SimParams {
-  width: u32,
-  height: u32,
-  cell_dimension: u32,
   cell_number_x: u32,
   cell_number_y: u32,
   total_cell_number: u32,
   number_colors: u32,
+  /// a cell is taken over when threshold_min <= number of enemies <= threshold_max
+  threshold_min: u32,
+  threshold_max: u32,
+  /// number of neighbors of a cell, the length of 'neighborhood'
+  neighborhood_size: u32,
+  /// how out of bound neighbors are handled, one of the BOUNDARY_* constants
+  boundary: u32,
+  /// color of the out of bound neighbors, for BOUNDARY_FIXED only
+  boundary_color: u32,
 };
 
+/// out of bound neighbors are ignored
+const BOUNDARY_CLIP: u32 = 0u;
+/// the grid is a torus
+const BOUNDARY_WRAP: u32 = 1u;
+/// the grid is mirrored on its edges
+const BOUNDARY_REFLECT: u32 = 2u;
+/// out of bound neighbors all have the color 'boundary_color'
+const BOUNDARY_FIXED: u32 = 3u;
+
 /// Cells are an unidimentional array (array<Cell>) to simplify data structure
-/// that why, we also transfer width, height and total number of cells which must be constant through the simulation
+/// that why, we also transfer the grid dimensions and total number of cells which must be constant through the simulation
 
 @group(0) @binding(0) var<uniform> params: SimParams;
 /// frame input
 @group(0) @binding(1) var<storage, read> cellSrc: array<u32>;
 /// frame output
 @group(0) @binding(2) var<storage, read_write> cellDst: array<u32>;
+/// offsets from a cell to each of its neighbors
+@group(0) @binding(3) var<storage, read> neighborhood: array<vec2<i32>>;
 
 @compute
 @workgroup_size(50)
-fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
-  let index = global_invocation_id.x; // cell index
+fn main(
+  @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+  @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+  // big grids are dispatched as rows of workgroups, a single one being limited to 65535 of them
+  let index = global_invocation_id.y * num_workgroups.x * 50u + global_invocation_id.x; // cell index
   if (index >= params.total_cell_number) {
     return;
   }
@@ -32,52 +53,31 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
   let x = i32(pos.x);
   let y = i32(pos.y);
 
-  var best_enemy_color = cell_color;
-  var number_of_best_enemy: u32 = 0;
-
-  var enemies: u32 = number_array_with_capacity(params.number_colors);
-  for (var xi = 0u; xi < 3; xi++) {
-    for (var yi = 0u; yi < 3; yi++) {
-      let xoff: i32 = i32(xi)-1;
-      let yoff: i32 = i32(yi)-1;
+  // a cell can only be taken over by the next color, so only neighbors of that color matter
+  let enemy_color = enemy_of(cell_color);
+  var number_of_enemies: u32 = 0;
 
-      // skip current cell
-      if xoff == 0 && yoff == 0 {
-        continue;
-      }
+  for (var i = 0u; i < params.neighborhood_size; i++) {
+    // neighbor position, brought back into the grid if out of bound
+    let xnei = map_coordinate(x+neighborhood[i].x, i32(params.cell_number_x));
+    let ynei = map_coordinate(y+neighborhood[i].y, i32(params.cell_number_y));
 
-      // neighbor position
-      let xnei = x+xoff;
-      let ynei = y+yoff;
-
-      // check if out of bound
-      if xnei < 0 || xnei >= i32(params.cell_number_x){
-        continue;
-      }
-      if ynei < 0 || ynei >= i32(params.cell_number_y) {
-        continue;
-      }
-
-      let neighbor_index = from_pos_to_index(u32(xnei), u32(ynei));
-
-      // check if out of bound #2 (should be useless and removed)
-      if neighbor_index < 0 || neighbor_index >= params.total_cell_number {
-        continue;
-      }
+    var neighbor_color: u32;
+    if xnei >= 0 && ynei >= 0 {
+      neighbor_color = cellSrc[from_pos_to_index(u32(xnei), u32(ynei))];
+    } else if params.boundary == BOUNDARY_FIXED {
+      neighbor_color = params.boundary_color;
+    } else {
+      continue;
+    }
 
-      let neighbor_color = cellSrc[neighbor_index];
-      if is_enemy(cell_color, neighbor_color) {
-        enemies = number_array_set(enemies, neighbor_color, number_array_get(enemies, neighbor_color)+1u);
-        if number_array_get(enemies, neighbor_color) > number_of_best_enemy {
-          best_enemy_color = neighbor_color;
-          number_of_best_enemy = number_array_get(enemies, neighbor_color);
-        }
-      }
+    if neighbor_color == enemy_color {
+      number_of_enemies++;
     }
   }
 
-  if number_of_best_enemy >= params.number_colors {
-    cell_color = best_enemy_color;
+  if number_of_enemies >= params.threshold_min && number_of_enemies <= params.threshold_max {
+    cell_color = enemy_color;
   }
 
   // Write back
@@ -86,54 +86,48 @@ fn main(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
 
 // HELPERS
 
-fn is_enemy(your_color: u32, other_color: u32) -> bool {
-  return (your_color+1)%params.number_colors == other_color;
+/// the only color that can take over a cell of color 'your_color'
+fn enemy_of(your_color: u32) -> u32 {
+  return (your_color+1)%params.number_colors;
 }
 
-fn from_index_to_pos(index: u32) -> vec2<u32> {
-  return vec2<u32>(index%params.cell_number_x, index/params.cell_number_y);
-}
-
-fn from_pos_to_index(col: u32, raw: u32) -> u32 {
-  return raw * params.cell_number_x + col;
-}
-
-// array in number
-
-fn number_array_with_capacity(n: u32) -> u32 {
-    return power(10u, n);
-}
+/// brings back coordinate 'c' of an axis of length 'len' into the grid, -1 if it stays outside
+///
+/// '%' is only used on positive operands, the sign of the result being left to the backends otherwise
+fn map_coordinate(c: i32, len: i32) -> i32 {
+  if c >= 0 && c < len {
+    return c;
+  }
 
-fn power(base: u32, exponent: u32) -> u32 {
-    var result: u32 = 1;
-    var x: u32 = base;
-    var n: u32 = exponent;
-
-    while (n != 0) {
-        if ((n & 1) != 0) {
-            result *= x;
-        }
-        x *= x;
-        n = n >> 1;
+  switch params.boundary {
+    case BOUNDARY_WRAP: {
+      if c < 0 {
+        return len - 1 - (-c - 1) % len;
+      }
+      return c % len;
     }
-
-    return result;
+    case BOUNDARY_REFLECT: {
+      if len == 1 {
+        return 0;
+      }
+      // reflections are symmetric around 0 and repeat every 2*(len-1) cells
+      let period = 2 * (len - 1);
+      let r = abs(c) % period;
+      if r >= len {
+        return period - r;
+      }
+      return r;
+    }
+    default: {
+      return -1;
+    }
+  }
 }
 
-/// no out of bound checking
-fn number_array_get(arr: u32, index: u32) -> u32 {
-    return (arr / power(10u, index)) % 10;
+fn from_index_to_pos(index: u32) -> vec2<u32> {
+  return vec2<u32>(index%params.cell_number_x, index/params.cell_number_x);
 }
 
-fn number_array_set(arr: u32, index: u32, new_num: u32) -> u32 {
-    var new_arr: u32 = arr;
-
-    let curr_num: u32 = number_array_get(arr, index);
-    let diff: i32 = i32(new_num) - i32(curr_num);
-    if diff < 0 {
-        new_arr -= u32(-diff) * power(10u, index);
-    } else {
-        new_arr += u32(diff) * power(10u, index);
-    }
-    return new_arr;
+fn from_pos_to_index(col: u32, raw: u32) -> u32 {
+  return raw * params.cell_number_x + col;
 }
@@ -1,11 +1,31 @@
 struct SimParams {
-  width: u32,
-  height: u32,
-  cell_dimension: u32,
   cell_number_x: u32,
   cell_number_y: u32,
   total_cell_number: u32,
   number_colors: u32,
+  /// a cell is taken over when threshold_min <= number of enemies <= threshold_max
+  threshold_min: u32,
+  threshold_max: u32,
+  /// number of neighbors of a cell, the length of 'neighborhood'
+  neighborhood_size: u32,
+  /// how out of bound neighbors are handled, one of the BOUNDARY_* constants
+  boundary: u32,
+  /// color of the out of bound neighbors, for BOUNDARY_FIXED only
+  boundary_color: u32,
+};
+
+/// where the grid is drawn, in clip space
+struct Layout {
+  /// size of a cell
+  cell_size: vec2<f32>,
+  /// bottom left corner of the grid
+  origin: vec2<f32>,
+};
+
+/// which part of the grid is displayed, 'center' being shown at the center of the window
+struct Camera {
+  center: vec2<f32>,
+  zoom: f32,
 };
 
 struct VertexOutput {
@@ -16,6 +36,8 @@ struct VertexOutput {
 @group(0) @binding(0) var<uniform> params: SimParams;
 /// color index to the real srgb color
 @group(0) @binding(1) var<storage, read> colormap: array<f32>; // size of the number of color
+@group(0) @binding(2) var<uniform> grid_layout: Layout;
+@group(0) @binding(3) var<uniform> camera: Camera;
 
 
 @vertex
@@ -26,19 +48,12 @@ fn main_vs(
 ) -> VertexOutput {
   let rawcolpos = from_index_to_pos(cell_index);
 
-  let offset = 0.5*vec2<f32>(
-   2.0-f32(params.cell_number_x-1u)*f32(params.cell_dimension)*(2.0/(f32(params.width))),
-   2.0-f32(params.cell_number_y-1u)*f32(params.cell_dimension)*(2.0/(f32(params.height))),
-  );
-
-  let pos = vec2<f32>(
-    -1.0+f32(rawcolpos.x)*f32(params.cell_dimension)*(2.0/(f32(params.width))),
-    -1.0+f32(rawcolpos.y)*f32(params.cell_dimension)*(2.0/(f32(params.height)))
-  );
+  let center = grid_layout.origin+(vec2<f32>(rawcolpos)+0.5)*grid_layout.cell_size;
 
   var out: VertexOutput;
   out.color_index = color_index;
-  out.position = vec4<f32>(offset + pos + vspos, 0.0, 1.0);
+  let position = center + 0.5*grid_layout.cell_size*vspos;
+  out.position = vec4<f32>((position - camera.center)*camera.zoom, 0.0, 1.0);
 
   return out;
 }
@@ -52,5 +67,5 @@ fn main_fs(in: VertexOutput) -> @location(0) vec4<f32> {
 // HELPERS
 
 fn from_index_to_pos(index: u32) -> vec2<u32> {
-  return vec2<u32>(index%params.cell_number_x, index/params.cell_number_y);
+  return vec2<u32>(index%params.cell_number_x, index/params.cell_number_x);
 }
\ No newline at end of file
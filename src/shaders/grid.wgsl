@@ -0,0 +1,105 @@
+struct SimParams {
+  cell_number_x: u32,
+  cell_number_y: u32,
+  total_cell_number: u32,
+  number_colors: u32,
+  /// a cell is taken over when threshold_min <= number of enemies <= threshold_max
+  threshold_min: u32,
+  threshold_max: u32,
+  /// number of neighbors of a cell, the length of 'neighborhood'
+  neighborhood_size: u32,
+  /// how out of bound neighbors are handled, one of the BOUNDARY_* constants
+  boundary: u32,
+  /// color of the out of bound neighbors, for BOUNDARY_FIXED only
+  boundary_color: u32,
+};
+
+/// where the grid is drawn, in clip space
+struct Layout {
+  /// size of a cell
+  cell_size: vec2<f32>,
+  /// bottom left corner of the grid
+  origin: vec2<f32>,
+};
+
+/// which part of the grid is displayed, 'center' being shown at the center of the window
+struct Camera {
+  center: vec2<f32>,
+  zoom: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    /// same as 'position', but interpolated for the fragment
+    @location(0) clip: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: SimParams;
+/// color index to the real srgb color
+@group(0) @binding(1) var<storage, read> colormap: array<f32>; // size of the number of color
+@group(0) @binding(2) var<uniform> grid_layout: Layout;
+@group(0) @binding(3) var<uniform> camera: Camera;
+/// the cells of the current generation
+@group(0) @binding(4) var<storage, read> cells: array<u32>;
+
+
+/// a single triangle covering the whole window, from 3 vertices without any buffer
+@vertex
+fn main_vs(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+  let clip = vec2<f32>(f32(vertex_index & 1u) * 4.0 - 1.0, f32(vertex_index >> 1u) * 4.0 - 1.0);
+
+  var out: VertexOutput;
+  out.position = vec4<f32>(clip, 0.0, 1.0);
+  out.clip = clip;
+
+  return out;
+}
+
+/// each pixel takes the color of the cell under it
+@fragment
+fn main_fs_nearest(in: VertexOutput) -> @location(0) vec4<f32> {
+  let pos = grid_position(in.clip);
+  if !in_grid(pos) {
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+  }
+
+  return vec4<f32>(color_of(vec2<u32>(pos)), 1.0);
+}
+
+/// each pixel blends the colors of the four cells around it, weighted by the distance to their centers
+@fragment
+fn main_fs_smooth(in: VertexOutput) -> @location(0) vec4<f32> {
+  let pos = grid_position(in.clip);
+  if !in_grid(pos) {
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+  }
+
+  // bottom left of the four cell centers around the pixel, the edge cells being repeated
+  let last = vec2<f32>(f32(params.cell_number_x - 1u), f32(params.cell_number_y - 1u));
+  let from_center = pos - 0.5;
+  let bottom_left = floor(from_center);
+  let t = from_center - bottom_left;
+  let p0 = vec2<u32>(clamp(bottom_left, vec2<f32>(0.0), last));
+  let p1 = vec2<u32>(clamp(bottom_left + 1.0, vec2<f32>(0.0), last));
+
+  let bottom = mix(color_of(p0), color_of(vec2<u32>(p1.x, p0.y)), t.x);
+  let top = mix(color_of(vec2<u32>(p0.x, p1.y)), color_of(p1), t.x);
+  return vec4<f32>(mix(bottom, top, t.y), 1.0);
+}
+
+// HELPERS
+
+/// position in cells of what is displayed at 'clip', the grid going from (0, 0) to (cell_number_x, cell_number_y)
+fn grid_position(clip: vec2<f32>) -> vec2<f32> {
+  let unzoomed = clip / camera.zoom + camera.center;
+  return (unzoomed - grid_layout.origin) / grid_layout.cell_size;
+}
+
+fn in_grid(pos: vec2<f32>) -> bool {
+  return pos.x >= 0.0 && pos.y >= 0.0 && pos.x < f32(params.cell_number_x) && pos.y < f32(params.cell_number_y);
+}
+
+fn color_of(pos: vec2<u32>) -> vec3<f32> {
+  let index_start = cells[pos.y * params.cell_number_x + pos.x] * 3u;
+  return vec3<f32>(colormap[index_start], colormap[index_start + 1u], colormap[index_start + 2u]);
+}
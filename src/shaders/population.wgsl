@@ -0,0 +1,64 @@
+struct SimParams {
+  cell_number_x: u32,
+  cell_number_y: u32,
+  total_cell_number: u32,
+  number_colors: u32,
+  /// a cell is taken over when threshold_min <= number of enemies <= threshold_max
+  threshold_min: u32,
+  threshold_max: u32,
+  /// number of neighbors of a cell, the length of 'neighborhood'
+  neighborhood_size: u32,
+  /// how out of bound neighbors are handled, one of the BOUNDARY_* constants
+  boundary: u32,
+  /// color of the out of bound neighbors, for BOUNDARY_FIXED only
+  boundary_color: u32,
+};
+
+/// must match the '@workgroup_size' below
+const WORKGROUP_SIZE: u32 = 256u;
+/// colors counted in workgroup memory first, the others (if any) go straight to 'counts'
+const SHARED_COLORS: u32 = 256u;
+
+@group(0) @binding(0) var<uniform> params: SimParams;
+/// cells of the generation to count
+@group(0) @binding(1) var<storage, read> cells: array<u32>;
+/// number of cells of each color, zeroed before the first workgroup
+@group(0) @binding(2) var<storage, read_write> counts: array<atomic<u32>>;
+
+/// counts of the cells of this workgroup, added once to 'counts' so that the global atomics
+/// are hit once per color and workgroup instead of once per cell
+var<workgroup> shared_counts: array<atomic<u32>, SHARED_COLORS>;
+
+@compute
+@workgroup_size(256)
+fn main(
+  @builtin(global_invocation_id) global_invocation_id: vec3<u32>,
+  @builtin(local_invocation_index) local_index: u32,
+  @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+  for (var color = local_index; color < SHARED_COLORS; color += WORKGROUP_SIZE) {
+    atomicStore(&shared_counts[color], 0u);
+  }
+  workgroupBarrier();
+
+  // no early return, every invocation must reach the barriers
+  // dispatched as rows of workgroups, like the compute shader
+  let index = global_invocation_id.y * num_workgroups.x * WORKGROUP_SIZE + global_invocation_id.x;
+  if index < params.total_cell_number {
+    let color = cells[index];
+    if color < SHARED_COLORS {
+      atomicAdd(&shared_counts[color], 1u);
+    } else {
+      atomicAdd(&counts[color], 1u);
+    }
+  }
+  workgroupBarrier();
+
+  let shared_colors = min(SHARED_COLORS, params.number_colors);
+  for (var color = local_index; color < shared_colors; color += WORKGROUP_SIZE) {
+    let count = atomicLoad(&shared_counts[color]);
+    if count > 0u {
+      atomicAdd(&counts[color], count);
+    }
+  }
+}
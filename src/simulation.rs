@@ -0,0 +1,559 @@
+use std::{borrow::Cow, sync::Arc};
+
+use crate::{
+    app::AppState,
+    population::{Population, PopulationCounter},
+    rule::{self, Rule},
+};
+use wgpu::util::DeviceExt;
+
+const CELLS_PER_GROUP: u32 = 50; // lower is better perfomance, but too low is complete madness
+
+/// most generations recorded in a single command buffer, so that the gpu is never stuck on one for too long
+pub const MAX_STEPS_PER_SUBMIT: u32 = 1024;
+
+/// represent the size in bytes taken by an 'u32' in memory, which is 4
+pub const SIZE_OF_U32: u64 = std::mem::size_of::<u32>() as u64;
+
+/// Everything needed to run the automaton on the gpu, without any window or surface attached
+pub struct Simulation {
+    /// shared with the simulations started over on the same device
+    pub device: Arc<wgpu::Device>,
+    pub queue: Arc<wgpu::Queue>,
+
+    // buffers
+    sim_param_buffer: wgpu::Buffer,
+    cells_buffers: Vec<wgpu::Buffer>, // src and dst so only 2
+
+    /// what computes the next generations
+    stepper: Stepper,
+    // grid dimension and rule, needed by the cpu fallback
+    cell_number_x: u32,
+    cell_number_y: u32,
+    rule: Rule,
+
+    /// number of generations computed since the start of the simulation
+    pub generation: usize,
+
+    /// count the cells of each color at every generation
+    count_populations: bool,
+    /// populations counted on the cpu, not taken yet
+    populations: Vec<Population>,
+}
+
+enum Stepper {
+    /// the compute shader does the work
+    Gpu {
+        cells_compute_bind_groups: Vec<wgpu::BindGroup>,
+        compute_pipeline: wgpu::ComputePipeline,
+        /// workgroups dispatched in x and y
+        work_group_count: (u32, u32),
+        /// counts the populations after each step, if they're wanted
+        population_counter: Option<Box<PopulationCounter>>,
+    },
+    /// the adapter can't run compute shaders, the cells of the current generation are
+    /// kept on the cpu and each new generation is uploaded to the gpu
+    Cpu { cells: Vec<u32> },
+}
+
+impl Simulation {
+    /// Request a device from `adapter` and create the simulation on it
+    ///
+    /// If the adapter does not support compute shaders, generations are computed on the cpu
+    /// with the reference implementation instead.
+    pub async fn new(adapter: &wgpu::Adapter, state: &AppState) -> Self {
+        // check that compute shader are supported
+        let downlevel_capabilities = adapter.get_downlevel_capabilities();
+        let compute_supported = downlevel_capabilities
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        if !compute_supported {
+            log::warn!("Adapter does not support compute shaders, falling back to the cpu");
+        }
+
+        // request device
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Device descriptor"),
+                    required_features: wgpu::Features::empty(),
+                    required_limits: if compute_supported {
+                        wgpu::Limits::downlevel_defaults()
+                    } else {
+                        // the compute shader limits are irrelevant, ask for whatever the adapter has
+                        adapter.limits()
+                    },
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        Self::with_device(Arc::new(device), Arc::new(queue), compute_supported, state)
+    }
+
+    /// A new simulation of the grid described by `state` on the device of this one,
+    /// to start over without requesting another device
+    pub fn restart(&self, state: &AppState) -> Self {
+        let compute_supported = matches!(self.stepper, Stepper::Gpu { .. });
+        Self::with_device(
+            self.device.clone(),
+            self.queue.clone(),
+            compute_supported,
+            state,
+        )
+    }
+
+    /// Create the buffers and pipelines of the simulation on an existing device
+    fn with_device(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        compute_supported: bool,
+        state: &AppState,
+    ) -> Self {
+        // buffer for simulation parameters uniform
+
+        let sim_param_data = state.sim_params();
+        let sim_param_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Simulation Parameter Buffer"),
+            contents: bytemuck::cast_slice(&sim_param_data),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // buffer for all cell color
+
+        let initial_cell_data = match &state.initial_cells {
+            Some(cells) => cells.clone(),
+            None => state.init.cells(
+                state.cell_number_x,
+                state.cell_number_y,
+                state.color_number,
+                &state.color_draw,
+                &mut state.cells_rng(),
+            ),
+        };
+
+        // creates two buffers of cell data each of size total_cell_number
+        // the two buffers alternate as dst and src for each frame
+
+        let mut cells_buffers = Vec::<wgpu::Buffer>::new();
+        for i in 0..2 {
+            cells_buffers.push(
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("Cell Buffer {i}")),
+                    contents: bytemuck::cast_slice(&initial_cell_data),
+                    // storage for the compute shader and the fullscreen renderer, vertex for the quads one
+                    usage: wgpu::BufferUsages::VERTEX
+                        | wgpu::BufferUsages::STORAGE
+                        | wgpu::BufferUsages::COPY_DST
+                        | wgpu::BufferUsages::COPY_SRC,
+                }),
+            );
+        }
+
+        // the first generation isn't computed, so it's counted here
+        let count_populations = state.population_path.is_some();
+        let populations = if count_populations {
+            vec![Population::count(
+                state.initial_generation,
+                &initial_cell_data,
+                state.color_number,
+            )]
+        } else {
+            vec![]
+        };
+
+        let stepper = if compute_supported {
+            Self::create_gpu_stepper(&device, &sim_param_buffer, &cells_buffers, state)
+        } else {
+            Stepper::Cpu {
+                cells: initial_cell_data,
+            }
+        };
+
+        Self {
+            device,
+            queue,
+
+            sim_param_buffer,
+            cells_buffers,
+
+            stepper,
+            cell_number_x: state.cell_number_x,
+            cell_number_y: state.cell_number_y,
+            rule: state.rule(),
+
+            generation: state.initial_generation,
+
+            count_populations,
+            populations,
+        }
+    }
+
+    fn create_gpu_stepper(
+        device: &wgpu::Device,
+        sim_param_buffer: &wgpu::Buffer,
+        cells_buffers: &[wgpu::Buffer],
+        state: &AppState,
+    ) -> Stepper {
+        let compute_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Compute shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("./shaders/compute.wgsl"))),
+        });
+
+        // buffer for the neighbor offsets, (x, y) pairs
+
+        let neighborhood_data = state
+            .neighborhood
+            .iter()
+            .flat_map(|&(xoff, yoff)| [xoff, yoff])
+            .collect::<Vec<i32>>();
+        let neighborhood_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Neighborhood Buffer"),
+            contents: bytemuck::cast_slice(&neighborhood_data),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        // create compute bind layout group and compute pipeline layout
+
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(sim_param_buffer.size()),
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                // shader variable 'cellSrc' is of type array<u32> of len total_cell_number
+                                (state.total_cell_number as u64) * SIZE_OF_U32,
+                            ),
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                // shader variable 'cellDst' is of type array<u32> of len total_cell_number
+                                (state.total_cell_number as u64) * SIZE_OF_U32,
+                            ),
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                // shader variable 'neighborhood' is of type array<vec2<i32>> of len neighborhood_size
+                                neighborhood_buffer.size(),
+                            ),
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("Compute bind groups"),
+            });
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("compute pipeline layout"),
+                bind_group_layouts: &[&compute_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        // create compute pipeline
+
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Compute pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &compute_shader,
+            entry_point: "main",
+        });
+
+        // create two bind groups, one for each buffer as the src
+        // where the alternate buffer is used as the dst
+
+        let mut cells_compute_bind_groups = Vec::<wgpu::BindGroup>::new();
+        for i in 0..2 {
+            cells_compute_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &compute_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: sim_param_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: cells_buffers[i].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: cells_buffers[(i + 1) % 2].as_entire_binding(), // bind to opposite buffer
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: neighborhood_buffer.as_entire_binding(),
+                    },
+                ],
+                label: Some(&format!("compute bind group {i}")),
+            }));
+        }
+
+        // calculates number of work groups from CELLS_PER_GROUP constant
+        let work_group_count = work_group_count(device, state.total_cell_number, CELLS_PER_GROUP);
+
+        Stepper::Gpu {
+            cells_compute_bind_groups,
+            compute_pipeline,
+            work_group_count,
+            population_counter: state.population_path.is_some().then(|| {
+                Box::new(PopulationCounter::new(
+                    device,
+                    sim_param_buffer,
+                    state.total_cell_number,
+                    state.color_number,
+                ))
+            }),
+        }
+    }
+
+    /// uniform buffer holding the simulation parameters, shared with the draw shader
+    pub fn sim_param_buffer(&self) -> &wgpu::Buffer {
+        &self.sim_param_buffer
+    }
+
+    /// one of the two buffers holding the cells, alternately the current one
+    pub fn cells_buffer(&self, i: usize) -> &wgpu::Buffer {
+        &self.cells_buffers[i]
+    }
+
+    /// buffer holding the cells of the current generation
+    pub fn current_cells_buffer(&self) -> &wgpu::Buffer {
+        &self.cells_buffers[self.generation % 2]
+    }
+
+    /// Set the cells within `radius` of `center` to `color`, the ones outside of the grid being ignored
+    ///
+    /// The cells of the current generation are overwritten, the next generations are computed from them
+    pub fn paint(&mut self, (cx, cy): (i32, i32), radius: u32, color: u32) {
+        let r = radius as i32;
+        let (width, height) = (self.cell_number_x as i32, self.cell_number_y as i32);
+        for y in (cy - r).max(0)..=(cy + r).min(height - 1) {
+            // the disk is painted one row at a time, each being contiguous in the buffer
+            let half_width = ((r * r - (y - cy) * (y - cy)) as f32).sqrt() as i32;
+            let (xmin, xmax) = ((cx - half_width).max(0), (cx + half_width).min(width - 1));
+            if xmin > xmax {
+                continue;
+            }
+
+            let row = vec![color; (xmax - xmin + 1) as usize];
+            let start = (y * width + xmin) as usize;
+            // the cpu fallback steps from its own copy of the cells
+            if let Stepper::Cpu { cells } = &mut self.stepper {
+                cells[start..start + row.len()].copy_from_slice(&row);
+            }
+            self.queue.write_buffer(
+                self.current_cells_buffer(),
+                start as u64 * SIZE_OF_U32,
+                bytemuck::cast_slice(&row),
+            );
+        }
+    }
+
+    /// Record the next `generations` generations into `command_encoder`, all in one compute pass
+    ///
+    /// With the cpu fallback, the generations are computed right away and the upload of the
+    /// last one is queued before `command_encoder` gets submitted
+    pub fn encode_steps(&mut self, command_encoder: &mut wgpu::CommandEncoder, generations: u32) {
+        if generations == 0 {
+            return;
+        }
+
+        match &mut self.stepper {
+            Stepper::Gpu {
+                cells_compute_bind_groups,
+                compute_pipeline,
+                work_group_count,
+                population_counter,
+            } => {
+                let count_batch = population_counter.as_mut().map(|counter| {
+                    counter.batch(
+                        &self.device,
+                        command_encoder,
+                        &self.sim_param_buffer,
+                        &self.cells_buffers,
+                        generations,
+                    )
+                });
+
+                command_encoder.push_debug_group("compute cell next frames");
+                {
+                    // compute pass, each dispatch reads what the previous one wrote
+                    let mut cpass =
+                        command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                            label: None,
+                            timestamp_writes: None,
+                        });
+                    for i in 0..generations as usize {
+                        cpass.set_pipeline(compute_pipeline);
+                        cpass.set_bind_group(
+                            0,
+                            &cells_compute_bind_groups[(self.generation + i) % 2],
+                            &[],
+                        );
+                        cpass.dispatch_workgroups(work_group_count.0, work_group_count.1, 1);
+
+                        // counts the generation just written, in the other buffer
+                        if let (Some(counter), Some(batch)) = (&*population_counter, &count_batch) {
+                            counter.dispatch(
+                                &mut cpass,
+                                batch,
+                                i as u32,
+                                (self.generation + i + 1) % 2,
+                            );
+                        }
+                    }
+                }
+                command_encoder.pop_debug_group();
+
+                if let (Some(counter), Some(batch)) = (population_counter, count_batch) {
+                    counter.finish_batch(command_encoder, batch, self.generation + 1);
+                }
+            }
+            Stepper::Cpu { cells } => {
+                for i in 1..=generations as usize {
+                    *cells = rule::step(cells, self.cell_number_x, self.cell_number_y, &self.rule);
+                    if self.count_populations {
+                        self.populations.push(Population::count(
+                            self.generation + i,
+                            cells,
+                            self.rule.colors,
+                        ));
+                    }
+                }
+                // write to the buffer that will be the current one
+                self.queue.write_buffer(
+                    &self.cells_buffers[(self.generation + generations as usize) % 2],
+                    0,
+                    bytemuck::cast_slice(cells),
+                );
+            }
+        }
+
+        // update generation count
+        self.generation += generations as usize;
+    }
+
+    /// Compute the next `generations` generations right away, in batches of at most `MAX_STEPS_PER_SUBMIT`
+    pub fn steps(&mut self, generations: u32) {
+        let mut remaining = generations;
+        while remaining > 0 {
+            let batch = remaining.min(MAX_STEPS_PER_SUBMIT);
+            let mut command_encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            self.encode_steps(&mut command_encoder, batch);
+            self.queue.submit(Some(command_encoder.finish()));
+            remaining -= batch;
+        }
+    }
+
+    /// Populations counted since the last call, oldest first, none if they aren't counted
+    ///
+    /// The ones computed by the gpu come back without blocking, a frame or two later,
+    /// unless `wait` which blocks until all the submitted generations are counted
+    pub fn populations(&mut self, wait: bool) -> Vec<Population> {
+        let mut populations = std::mem::take(&mut self.populations);
+        if let Stepper::Gpu {
+            population_counter: Some(counter),
+            ..
+        } = &mut self.stepper
+        {
+            populations.extend(counter.take(&self.device, wait));
+        }
+        populations
+    }
+
+    /// Copy the cells of the current generation back to the cpu, blocks until the gpu is done
+    pub fn read_cells(&self) -> Vec<u32> {
+        if let Stepper::Cpu { cells } = &self.stepper {
+            return cells.clone();
+        }
+
+        let cells_buffer = self.current_cells_buffer();
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cells Staging Buffer"),
+            size: cells_buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut command_encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        command_encoder.copy_buffer_to_buffer(
+            cells_buffer,
+            0,
+            &staging_buffer,
+            0,
+            cells_buffer.size(),
+        );
+        self.queue.submit(Some(command_encoder.finish()));
+
+        bytemuck::cast_slice(&read_staging_buffer(&self.device, &staging_buffer)).to_vec()
+    }
+}
+
+/// Workgroups of `cells_per_group` to dispatch for `cell_number` cells, in rows as long
+/// as the device allows so that big grids don't go over the limit of a single dimension
+pub fn work_group_count(
+    device: &wgpu::Device,
+    cell_number: u32,
+    cells_per_group: u32,
+) -> (u32, u32) {
+    let groups = cell_number.div_ceil(cells_per_group);
+    let row = groups.min(device.limits().max_compute_workgroups_per_dimension);
+    (row, groups.div_ceil(row))
+}
+
+/// Most cells a grid can have, each cells buffer being bound whole to the shaders
+pub fn max_cell_number() -> u32 {
+    (wgpu::Limits::downlevel_defaults().max_storage_buffer_binding_size as u64 / SIZE_OF_U32) as u32
+}
+
+/// Most neighbors a cell can have, the neighborhood buffer being bound whole to the compute shader
+pub fn max_neighborhood_size() -> usize {
+    // an (x, y) pair of i32 per neighbor
+    wgpu::Limits::downlevel_defaults().max_storage_buffer_binding_size as usize
+        / (2 * SIZE_OF_U32 as usize)
+}
+
+/// Content of a `MAP_READ` buffer, once the copies to it already submitted are done
+pub fn read_staging_buffer(device: &wgpu::Device, staging_buffer: &wgpu::Buffer) -> Vec<u8> {
+    let buffer_slice = staging_buffer.slice(..);
+    buffer_slice.map_async(wgpu::MapMode::Read, |result| {
+        result.expect("Failed to map the staging buffer")
+    });
+    device.poll(wgpu::Maintain::Wait);
+
+    let data = buffer_slice.get_mapped_range().to_vec();
+    staging_buffer.unmap();
+    data
+}
@@ -0,0 +1,308 @@
+//! Snapshots of a whole simulation, to resume it later exactly where it was
+//!
+//! A snapshot is a little endian binary file starting with `MAGIC` and the version of its format,
+//! each version keeping its own reader so that old snapshots can still be loaded.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    app::{AppState, MAX_COLOR_NUMBER, MIN_COLOR_NUMBER},
+    export::{invalid, numbered_path},
+    rule::Boundary,
+    simulation::Simulation,
+};
+
+const MAGIC: &[u8; 8] = b"SAXRUMFX";
+/// version of the snapshots being written
+const VERSION: u32 = 1;
+
+/// Everything needed to resume a simulation
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub generation: u64,
+    pub seed: u64,
+
+    pub cell_number_x: u32,
+    pub cell_number_y: u32,
+
+    pub color_number: u32,
+    pub threshold: RangeInclusive<u32>,
+    pub neighborhood: Vec<(i32, i32)>,
+    pub boundary: Boundary,
+
+    /// 3 linear f32 per color
+    pub colormap: Vec<f32>,
+    /// row by row, the first row being the bottom one
+    pub cells: Vec<u32>,
+}
+
+impl Snapshot {
+    /// Snapshot of the simulation described by `state`, at `generation` whose cells are `cells`
+    pub fn new(state: &AppState, generation: usize, cells: Vec<u32>) -> Self {
+        Self {
+            generation: generation as u64,
+            seed: state.seed,
+
+            cell_number_x: state.cell_number_x,
+            cell_number_y: state.cell_number_y,
+
+            color_number: state.color_number,
+            threshold: state.threshold.clone(),
+            neighborhood: state.neighborhood.clone(),
+            boundary: state.boundary,
+
+            colormap: state.colormap.clone(),
+            cells,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+
+        file.write_all(&self.generation.to_le_bytes())?;
+        file.write_all(&self.seed.to_le_bytes())?;
+        for n in [self.cell_number_x, self.cell_number_y, self.color_number] {
+            file.write_all(&n.to_le_bytes())?;
+        }
+        for n in [*self.threshold.start(), *self.threshold.end()] {
+            file.write_all(&n.to_le_bytes())?;
+        }
+        for n in self.boundary.as_params() {
+            file.write_all(&n.to_le_bytes())?;
+        }
+        file.write_all(&(self.neighborhood.len() as u32).to_le_bytes())?;
+        for &(xoff, yoff) in &self.neighborhood {
+            file.write_all(&xoff.to_le_bytes())?;
+            file.write_all(&yoff.to_le_bytes())?;
+        }
+        for c in &self.colormap {
+            file.write_all(&c.to_le_bytes())?;
+        }
+        for cell in &self.cells {
+            file.write_all(&cell.to_le_bytes())?;
+        }
+        file.flush()
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+
+        let mut magic = [0; MAGIC.len()];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a saxrumfex snapshot"));
+        }
+
+        let snapshot = match read_u32(&mut file)? {
+            1 => Self::read_v1(&mut file, file_len)?,
+            version => {
+                return Err(invalid(format!(
+                    "snapshot version {version} is newer than this saxrumfex (version {VERSION})"
+                )))
+            }
+        };
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    /// `file_len` bounds the size of the grid, which is checked before its cells are read
+    fn read_v1(file: &mut (impl Read + Seek), file_len: u64) -> io::Result<Self> {
+        let generation = read_u64(file)?;
+        let seed = read_u64(file)?;
+        let cell_number_x = read_u32(file)?;
+        let cell_number_y = read_u32(file)?;
+        let color_number = read_u32(file)?;
+        if !(MIN_COLOR_NUMBER..=MAX_COLOR_NUMBER).contains(&color_number) {
+            return Err(invalid("invalid number of colors"));
+        }
+        let threshold = read_u32(file)?..=read_u32(file)?;
+        let boundary = Boundary::from_params([read_u32(file)?, read_u32(file)?])
+            .ok_or_else(|| invalid("invalid boundary"))?;
+
+        let neighborhood_len = read_u32(file)?;
+        let mut neighborhood = vec![];
+        for _ in 0..neighborhood_len {
+            neighborhood.push((read_u32(file)? as i32, read_u32(file)? as i32));
+        }
+        let colormap = (0..color_number * 3)
+            .map(|_| read_u32(file).map(f32::from_bits))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let total_cell_number = cell_number_x
+            .checked_mul(cell_number_y)
+            .ok_or_else(|| invalid("invalid grid size"))?;
+        if total_cell_number as u64 * 4 > file_len.saturating_sub(file.stream_position()?) {
+            return Err(invalid(
+                "truncated snapshot, fewer cells than the grid size",
+            ));
+        }
+        let mut cells = vec![0_u32; total_cell_number as usize];
+        file.read_exact(bytemuck::cast_slice_mut(&mut cells))?;
+        for cell in &mut cells {
+            *cell = u32::from_le(*cell);
+        }
+
+        Ok(Self {
+            generation,
+            seed,
+            cell_number_x,
+            cell_number_y,
+            color_number,
+            threshold,
+            neighborhood,
+            boundary,
+            colormap,
+            cells,
+        })
+    }
+
+    /// Whether the snapshot describes a simulation that can run
+    fn validate(&self) -> io::Result<()> {
+        if self.cell_number_x == 0 || self.cell_number_y == 0 {
+            return Err(invalid("empty grid"));
+        }
+        if self.neighborhood.is_empty() {
+            return Err(invalid("empty neighborhood"));
+        }
+        // same as '--threshold'
+        let (min, max) = (*self.threshold.start(), *self.threshold.end());
+        if !(0 < min && min <= max) {
+            return Err(invalid("invalid threshold"));
+        }
+        if min as usize > self.neighborhood.len() {
+            return Err(invalid(
+                "threshold greater than the size of the neighborhood",
+            ));
+        }
+        if let Boundary::Fixed(color) = self.boundary {
+            if color >= self.color_number {
+                return Err(invalid("invalid boundary color"));
+            }
+        }
+        if self.cells.iter().any(|&cell| cell >= self.color_number) {
+            return Err(invalid("invalid cell color"));
+        }
+        Ok(())
+    }
+}
+
+/// Write the current generation of `simulation` next to `state.save_path`, returns where it was written
+pub fn save(simulation: &Simulation, state: &AppState) -> io::Result<PathBuf> {
+    let path = numbered_path(&state.save_path, simulation.generation);
+    Snapshot::new(state, simulation.generation, simulation.read_cells()).write(&path)?;
+    Ok(path)
+}
+
+fn read_u32(file: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    file.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(file: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            generation: 7,
+            seed: 42,
+            cell_number_x: 2,
+            cell_number_y: 1,
+            color_number: 2,
+            threshold: 1..=u32::MAX,
+            neighborhood: vec![(-1, 0), (1, 0)],
+            boundary: Boundary::Fixed(1),
+            colormap: vec![0.0, 0.5, 1.0, 1.0, 0.5, 0.0],
+            cells: vec![1, 0],
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("saxrumfex_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn write_v1_layout() {
+        let path = temp_path("layout.sxf");
+        snapshot().write(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut expected = b"SAXRUMFX".to_vec();
+        // version, then the generation and seed as two words each
+        let words: [u32; 5] = [1, 7, 0, 42, 0];
+        expected.extend(words.iter().flat_map(|n| n.to_le_bytes()));
+        // grid, colors, threshold, boundary, neighborhood
+        let words: [u32; 12] = [2, 1, 2, 1, u32::MAX, 3, 1, 2, -1_i32 as u32, 0, 1, 0];
+        expected.extend(words.iter().flat_map(|n| n.to_le_bytes()));
+        let colormap = [0.0_f32, 0.5, 1.0, 1.0, 0.5, 0.0];
+        expected.extend(colormap.iter().flat_map(|c| c.to_le_bytes()));
+        expected.extend([1_u32, 0].iter().flat_map(|n| n.to_le_bytes()));
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round_trip.sxf");
+        let written = snapshot();
+        written.write(&path).unwrap();
+        let read = Snapshot::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+
+        assert_eq!(read.generation, written.generation);
+        assert_eq!(read.seed, written.seed);
+        assert_eq!(
+            (read.cell_number_x, read.cell_number_y),
+            (written.cell_number_x, written.cell_number_y)
+        );
+        assert_eq!(read.color_number, written.color_number);
+        assert_eq!(read.threshold, written.threshold);
+        assert_eq!(read.neighborhood, written.neighborhood);
+        assert_eq!(read.boundary.as_params(), written.boundary.as_params());
+        assert_eq!(read.colormap, written.colormap);
+        assert_eq!(read.cells, written.cells);
+    }
+
+    #[test]
+    fn read_rejects_invalid() {
+        let path = temp_path("invalid.sxf");
+
+        // a huge grid announced by the header, but no cells
+        let mut huge = snapshot();
+        huge.cell_number_x = u16::MAX as u32;
+        huge.cell_number_y = u16::MAX as u32;
+        huge.write(&path).unwrap();
+        let truncated = Snapshot::read(&path);
+
+        let mut threshold = snapshot();
+        threshold.threshold = 3..=u32::MAX;
+        threshold.write(&path).unwrap();
+        let unreachable = Snapshot::read(&path);
+
+        threshold.threshold = 0..=1;
+        threshold.write(&path).unwrap();
+        let zero = Snapshot::read(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        for result in [truncated, unreachable, zero] {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,58 +1,239 @@
 use std::{
+    path::{Path, PathBuf},
     sync::Arc,
-    thread,
     time::{Duration, Instant},
 };
 
 use winit::{
-    event::{Event, KeyEvent, WindowEvent},
-    event_loop::EventLoop,
-    keyboard::{KeyCode, PhysicalKey},
+    dpi::PhysicalPosition,
+    event::{ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
     window::Window,
 };
 
-use crate::{app::AppState, graphics::WgpuContext};
+use crate::{
+    app::{AppState, Brush, Camera, Playback, Speed},
+    export::{cells_to_rgb8, numbered_path, write_png, FrameOutput, ScreenshotMode},
+    graphics::WgpuContext,
+    population::{Population, PopulationLog},
+    record::Recorder,
+    snapshot,
+};
 
-pub async fn run(event_loop: EventLoop<()>, window: Arc<Window>, state: AppState) {
+pub async fn run(event_loop: EventLoop<()>, window: Arc<Window>, mut state: AppState) {
     // let (width, height) = {
     //     let win_size = window.inner_size();
     //     (win_size.width, win_size.height)
     // };
 
+    // the window may not have the size asked for, in logical pixels with a scale factor or because of the window manager
+    let size = window.inner_size();
+    if (size.width, size.height) != (state.width, state.height) {
+        state.resize(size.width.max(1), size.height.max(1));
+    }
+
     let mut wgpu_context = WgpuContext::new(window, &state).await;
+    let mut playback = Playback::new(Speed::PerFrame(state.steps_per_frame));
+    let mut window_title = String::new();
+    let mut brush = Brush::new();
+    let mut cursor_position = PhysicalPosition::new(0.0, 0.0);
+    let mut camera = Camera::new();
+    let mut panning = false;
+    let mut modifiers = ModifiersState::empty();
+
+    // time between two frames, none when running as fast as possible
+    let frame_duration = match (state.fast, state.fps) {
+        (false, Some(fps)) => Some(Duration::from_secs_f32(1.0 / fps)),
+        _ => None,
+    };
+    let mut next_frame = Instant::now();
+    // window size to follow on the next frame, the window may be resized many times in between
+    let mut new_size = None;
+    let mut screenshot_at = state.screenshot_at;
+    let mut save_at = state.save_at;
+    let mut frame_output = FrameOutput::new(
+        state
+            .dump_frames
+            .clone()
+            .map(|dir| (dir, state.dump_format)),
+        state.pipe_raw,
+    )
+    .unwrap_or_else(|err| {
+        log::error!("Failed to dump frames: {err}");
+        None
+    });
+    // generation of the next frame to dump or pipe
+    let mut next_frame_output = state.initial_generation;
+    let mut recorder = if state.record_at_start {
+        start_recording(state.record_path.clone(), &state, state.initial_generation)
+    } else {
+        None
+    };
+
+    let mut population_log = state
+        .population_path
+        .as_ref()
+        .and_then(|path| create_population_log(path, &state));
+    // grids regenerated on resize so far, each logging its populations to its own file
+    let mut regenerations = 0;
 
     let main_window_id = wgpu_context.window.id();
     event_loop
         .run(move |event, target| match event {
             Event::WindowEvent { window_id, event } if window_id == main_window_id => match event {
                 WindowEvent::CloseRequested => target.exit(),
+                // a scale factor change is followed by this event, with the new size in physical pixels
+                WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                    new_size = Some(size)
+                }
                 WindowEvent::KeyboardInput {
                     event:
                         KeyEvent {
                             physical_key: PhysicalKey::Code(keycode),
+                            state: ElementState::Pressed,
                             ..
                         },
                     ..
                 } => {
                     match keycode {
-                        KeyCode::KeyS => {
-                            // toggle app pause
-                            wgpu_context.window.request_redraw();
+                        // toggle app pause
+                        KeyCode::KeyS => playback.paused = !playback.paused,
+                        // advance exactly one generation
+                        KeyCode::KeyN | KeyCode::ArrowRight => playback.step_once(),
+                        // change the number of generations per frame
+                        KeyCode::ArrowUp | KeyCode::Equal | KeyCode::NumpadAdd => {
+                            playback.speed = playback.speed.faster()
+                        }
+                        KeyCode::ArrowDown | KeyCode::Minus | KeyCode::NumpadSubtract => {
+                            playback.speed = playback.speed.slower()
+                        }
+                        // back to the whole grid
+                        KeyCode::Home => {
+                            camera = Camera::new();
+                            wgpu_context.set_camera(&camera);
+                        }
+                        KeyCode::F5 => save_snapshot(&wgpu_context, &state),
+                        KeyCode::F12 => save_screenshot(&mut wgpu_context, &state),
+                        // start or stop recording
+                        KeyCode::KeyR => {
+                            recorder = match recorder.take() {
+                                Some(recorder) => {
+                                    stop_recording(recorder);
+                                    None
+                                }
+                                None => {
+                                    let generation = wgpu_context.simulation.generation;
+                                    let path = numbered_path(&state.record_path, generation);
+                                    start_recording(path, &state, generation)
+                                }
+                            }
                         }
                         KeyCode::Escape => target.exit(),
-                        _ => {}
+                        // choose the color painted by the mouse
+                        _ => match digit(keycode) {
+                            Some(color) if color < state.color_number => brush.color = color,
+                            _ => {}
+                        },
+                    }
+                }
+                WindowEvent::ModifiersChanged(new_modifiers) => modifiers = new_modifiers.state(),
+                WindowEvent::CursorMoved { position, .. } => {
+                    if panning {
+                        let from = clip_position(cursor_position, &wgpu_context.window);
+                        let to = clip_position(position, &wgpu_context.window);
+                        camera.pan([to[0] - from[0], to[1] - from[1]]);
+                        wgpu_context.set_camera(&camera);
+                    }
+                    cursor_position = position;
+                    if brush.stroke.is_some() {
+                        let cell =
+                            cell_under(cursor_position, &wgpu_context.window, &state, &camera);
+                        for center in brush.stroke_to(cell) {
+                            wgpu_context
+                                .simulation
+                                .paint(center, brush.radius, brush.color);
+                        }
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state: button_state,
+                    button: MouseButton::Left,
+                    ..
+                } => match button_state {
+                    ElementState::Pressed => {
+                        let cell =
+                            cell_under(cursor_position, &wgpu_context.window, &state, &camera);
+                        for center in brush.stroke_to(cell) {
+                            wgpu_context
+                                .simulation
+                                .paint(center, brush.radius, brush.color);
+                        }
+                    }
+                    ElementState::Released => brush.stroke = None,
+                },
+                WindowEvent::MouseInput {
+                    state: button_state,
+                    button: MouseButton::Right | MouseButton::Middle,
+                    ..
+                } => panning = button_state == ElementState::Pressed,
+                WindowEvent::MouseWheel { delta, .. } => {
+                    // in lines
+                    let scroll = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(position) => {
+                            (position.y / PIXELS_PER_LINE) as f32
+                        }
+                    };
+                    if modifiers.shift_key() {
+                        // change the brush radius
+                        if scroll > 0.0 {
+                            brush.grow();
+                        } else if scroll < 0.0 {
+                            brush.shrink();
+                        }
+                    } else {
+                        // zoom on what's under the cursor
+                        let position = clip_position(cursor_position, &wgpu_context.window);
+                        camera.zoom_at(position, ZOOM_PER_LINE.powf(scroll));
+                        wgpu_context.set_camera(&camera);
                     }
                 }
                 WindowEvent::RedrawRequested => {
-                    // regulate frame rendering at 60fps max
-                    let since_last_frame = wgpu_context.since_last_frame.elapsed();
-                    if since_last_frame.as_micros() < 16666 {
-                        thread::sleep(Duration::from_micros(
-                            16666 - since_last_frame.as_micros() as u64,
-                        ));
+                    // some platforms send the current size when the window appears,
+                    // which mustn't throw away a loaded or exact grid
+                    if let Some(size) = new_size
+                        .take()
+                        .filter(|size| (size.width, size.height) != (state.width, state.height))
+                    {
+                        if state.regenerate_on_resize {
+                            // the recording can't go on with a different grid
+                            if let Some(recorder) = recorder.take() {
+                                stop_recording(recorder);
+                            }
+                            next_frame_output = 0;
+                            // the counts of the old grid still on their way
+                            let populations = wgpu_context.simulation.populations(true);
+                            write_populations(&mut population_log, &populations);
+                            state.regenerate(size.width, size.height);
+                            // generations start over, as a new file
+                            regenerations += 1;
+                            population_log = state.population_path.as_ref().and_then(|path| {
+                                create_population_log(&numbered_path(path, regenerations), &state)
+                            });
+                            wgpu_context.regenerate(&state);
+                            wgpu_context.set_camera(&camera);
+                        } else {
+                            state.resize(size.width, size.height);
+                            wgpu_context.resize(&state);
+                        }
+                    }
+
+                    // schedule the next frame, without trying to catch up if we're late
+                    if let Some(frame_duration) = frame_duration {
+                        next_frame = (next_frame + frame_duration).max(Instant::now());
                     }
 
-                    wgpu_context.since_last_frame = Instant::now();
                     // aquire new frame
                     let frame = match wgpu_context.surface.get_current_texture() {
                         Ok(frame) => frame,
@@ -65,9 +246,10 @@ pub async fn run(event_loop: EventLoop<()>, window: Arc<Window>, state: AppState
                             // If the surface is outdated, or was lost, reconfigure it.
                             wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost,
                         ) => {
-                            wgpu_context
-                                .surface
-                                .configure(&wgpu_context.device, &wgpu_context.surface_config);
+                            wgpu_context.surface.configure(
+                                &wgpu_context.simulation.device,
+                                &wgpu_context.surface_config,
+                            );
                             wgpu_context
                                 .surface
                                 .get_current_texture()
@@ -83,17 +265,235 @@ pub async fn run(event_loop: EventLoop<()>, window: Arc<Window>, state: AppState
                     });
 
                     // do the necessary computation to render the frame
-                    wgpu_context.render(&view, &state);
+                    let mut generations = playback.generations_for_next_frame();
+                    let generation = wgpu_context.simulation.generation;
+                    let next_capture = [
+                        screenshot_at,
+                        save_at,
+                        recorder.as_ref().map(Recorder::next_generation),
+                        frame_output.as_ref().map(|_| next_frame_output),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .filter(|&at| at >= generation)
+                    .min();
+                    if let Some(at) = next_capture {
+                        // don't go past the generation of the next screenshot, snapshot or recorded or output frame
+                        generations = generations.min((at - generation) as u32);
+                    }
+                    wgpu_context.render(&view, &state, generations);
+                    let populations = wgpu_context.simulation.populations(false);
+                    write_populations(&mut population_log, &populations);
+
+                    let generation = wgpu_context.simulation.generation;
+                    if screenshot_at == Some(generation) {
+                        save_screenshot(&mut wgpu_context, &state);
+                        screenshot_at = None;
+                    }
+                    if save_at == Some(generation) {
+                        save_snapshot(&wgpu_context, &state);
+                        save_at = None;
+                    }
+                    if let Some(output) = frame_output
+                        .as_mut()
+                        .filter(|_| next_frame_output == generation)
+                    {
+                        let written = grab_image(&mut wgpu_context, &state).and_then(
+                            |(width, height, rgb)| {
+                                output
+                                    .write(width, height, &rgb)
+                                    .map_err(|err| err.to_string())
+                            },
+                        );
+                        if let Err(err) = written {
+                            log::error!("Failed to output frame, no more will be: {err}");
+                            frame_output = None;
+                        }
+                        next_frame_output += state.record_every as usize;
+                    }
+                    if let Some(active) = recorder
+                        .as_mut()
+                        .filter(|r| r.next_generation() == generation)
+                    {
+                        match active.record(&wgpu_context.simulation.read_cells()) {
+                            Ok(false) => {}
+                            Ok(true) => stop_recording(recorder.take().unwrap()),
+                            Err(err) => {
+                                log::error!("Failed to record {}: {err}", active.path.display());
+                                recorder = None;
+                            }
+                        }
+                    }
+
+                    // show the current generation, only when it changed
+                    let new_title = title(wgpu_context.simulation.generation, &playback);
+                    if new_title != window_title {
+                        wgpu_context.window.set_title(&new_title);
+                        window_title = new_title;
+                    }
 
                     // show frame
                     frame.present();
-                    // draw next frame
-                    wgpu_context.window.request_redraw();
                 }
 
                 _ => {}
             },
+            // don't leave an unfinished recording
+            Event::LoopExiting => {
+                if let Some(recorder) = recorder.take() {
+                    stop_recording(recorder);
+                }
+                let populations = wgpu_context.simulation.populations(true);
+                write_populations(&mut population_log, &populations);
+            }
+            Event::AboutToWait => {
+                // regulate frame rendering without blocking the event loop, so that inputs stay responsive
+                if Instant::now() >= next_frame {
+                    wgpu_context.window.request_redraw();
+                } else {
+                    target.set_control_flow(ControlFlow::WaitUntil(next_frame));
+                }
+            }
             _ => {}
         })
         .unwrap();
 }
+
+/// Write the current generation as a PNG image, next to `state.screenshot_path`
+fn save_screenshot(wgpu_context: &mut WgpuContext, state: &AppState) {
+    let path = numbered_path(&state.screenshot_path, wgpu_context.simulation.generation);
+    match grab_image(wgpu_context, state).and_then(|(width, height, rgb)| {
+        write_png(&path, width, height, &rgb).map_err(|err| err.to_string())
+    }) {
+        Ok(()) => eprintln!("Screenshot written to {}", path.display()),
+        Err(err) => log::error!("Failed to write {}: {err}", path.display()),
+    }
+}
+
+/// Log of the populations written to `path`, none if it can't be created
+fn create_population_log(path: &Path, state: &AppState) -> Option<PopulationLog> {
+    PopulationLog::new(path, state.color_number)
+        .map_err(|err| log::error!("Failed to write {}: {err}", path.display()))
+        .ok()
+}
+
+/// Stops logging the populations if they can't be written
+fn write_populations(population_log: &mut Option<PopulationLog>, populations: &[Population]) {
+    if let Some(log) = population_log {
+        if let Err(err) = log.write(populations) {
+            log::error!("Failed to write the populations, no more will be: {err}");
+            *population_log = None;
+        }
+    }
+}
+
+fn save_snapshot(wgpu_context: &WgpuContext, state: &AppState) {
+    match snapshot::save(&wgpu_context.simulation, state) {
+        Ok(path) => eprintln!("Snapshot written to {}", path.display()),
+        Err(err) => log::error!("Failed to write the snapshot: {err}"),
+    }
+}
+
+/// The current generation as packed RGB pixels, what is shown depending on `state.screenshot_mode`
+fn grab_image(
+    wgpu_context: &mut WgpuContext,
+    state: &AppState,
+) -> Result<(u32, u32, Vec<u8>), String> {
+    match state.screenshot_mode {
+        ScreenshotMode::Raw => {
+            let cells = wgpu_context.simulation.read_cells();
+            Ok((
+                state.cell_number_x,
+                state.cell_number_y,
+                cells_to_rgb8(&cells, state.cell_number_x, &state.colormap),
+            ))
+        }
+        ScreenshotMode::Rendered => wgpu_context.capture(state).map(|rgb| {
+            (
+                wgpu_context.surface_config.width,
+                wgpu_context.surface_config.height,
+                rgb,
+            )
+        }),
+    }
+}
+
+/// Start recording to `path`, the current generation being `generation`
+fn start_recording(path: PathBuf, state: &AppState, generation: usize) -> Option<Recorder> {
+    match Recorder::new(
+        path,
+        state,
+        generation,
+        state.record_frames,
+        state.record_every,
+        // stopped with R, on regenerate or on exit
+        true,
+    ) {
+        Ok(recorder) => {
+            eprintln!("Recording to {}", recorder.path.display());
+            Some(recorder)
+        }
+        Err(err) => {
+            log::error!("Failed to start recording: {err}");
+            None
+        }
+    }
+}
+
+fn stop_recording(recorder: Recorder) {
+    let path = recorder.path.clone();
+    match recorder.finish() {
+        Ok(()) => eprintln!("Recording written to {}", path.display()),
+        Err(err) => log::error!("Failed to write {}: {err}", path.display()),
+    }
+}
+
+/// how much a line of scroll wheel zooms in
+const ZOOM_PER_LINE: f32 = 1.2;
+/// scroll of touchpads is given in pixels
+const PIXELS_PER_LINE: f64 = 20.0;
+
+/// `position`, in pixels from the top left corner of the window, in clip space (y going up)
+fn clip_position(position: PhysicalPosition<f64>, window: &Window) -> [f32; 2] {
+    let size = window.inner_size();
+    [
+        (2.0 * position.x / size.width as f64 - 1.0) as f32,
+        (1.0 - 2.0 * position.y / size.height as f64) as f32,
+    ]
+}
+
+/// Cell displayed at `position`, in pixels from the top left corner of the window
+fn cell_under(
+    position: PhysicalPosition<f64>,
+    window: &Window,
+    state: &AppState,
+    camera: &Camera,
+) -> (i32, i32) {
+    state.cell_at(camera.unproject(clip_position(position, window)))
+}
+
+/// Value of a number key, from the top row or the numpad
+fn digit(keycode: KeyCode) -> Option<u32> {
+    let digit = match keycode {
+        KeyCode::Digit0 | KeyCode::Numpad0 => 0,
+        KeyCode::Digit1 | KeyCode::Numpad1 => 1,
+        KeyCode::Digit2 | KeyCode::Numpad2 => 2,
+        KeyCode::Digit3 | KeyCode::Numpad3 => 3,
+        KeyCode::Digit4 | KeyCode::Numpad4 => 4,
+        KeyCode::Digit5 | KeyCode::Numpad5 => 5,
+        KeyCode::Digit6 | KeyCode::Numpad6 => 6,
+        KeyCode::Digit7 | KeyCode::Numpad7 => 7,
+        KeyCode::Digit8 | KeyCode::Numpad8 => 8,
+        KeyCode::Digit9 | KeyCode::Numpad9 => 9,
+        _ => return None,
+    };
+    Some(digit)
+}
+
+fn title(generation: usize, playback: &Playback) -> String {
+    if playback.paused {
+        format!("SAXRUMFEX - generation {generation} (paused)")
+    } else {
+        format!("SAXRUMFEX - generation {generation} ({})", playback.speed)
+    }
+}
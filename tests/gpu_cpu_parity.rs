@@ -0,0 +1,493 @@
+//! Cross validation of 'compute.wgsl' against a small cpu oracle of the cyclic rule
+//!
+//! Identical grids are seeded on both sides, then after each generation the gpu output
+//! is read back and must be bit-exact with the oracle. Any adapter will do (the fallback
+//! one included), if there is none at all the tests are skipped.
+
+use std::{borrow::Cow, ops::RangeInclusive, sync::OnceLock};
+
+use nanorand::{Rng, WyRand};
+use wgpu::util::DeviceExt;
+
+/// must match the '@workgroup_size' of the compute shader
+const CELLS_PER_GROUP: u32 = 50;
+
+struct Rule {
+    colors: u32,
+    threshold: RangeInclusive<u32>,
+    neighborhood: Vec<(i32, i32)>,
+    boundary: Boundary,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Boundary {
+    Clip,
+    Wrap,
+    Reflect,
+    Fixed(u32),
+}
+
+impl Rule {
+    /// the default rule of the app: as many enemies as there are colors are needed among the 8 closest
+    /// neighbors, or all of them when there are more colors
+    fn classic(colors: u32) -> Self {
+        let neighborhood = square(1, |_, _| true);
+        Self {
+            colors,
+            threshold: colors.min(neighborhood.len() as u32)..=u32::MAX,
+            neighborhood,
+            boundary: Boundary::Clip,
+        }
+    }
+}
+
+/// Offsets within the square of radius `r` accepted by `keep`, the center excluded
+fn square(r: i32, keep: impl Fn(i32, i32) -> bool) -> Vec<(i32, i32)> {
+    (-r..=r)
+        .flat_map(|dx| (-r..=r).map(move |dy| (dx, dy)))
+        .filter(|&(dx, dy)| (dx, dy) != (0, 0) && keep(dx, dy))
+        .collect()
+}
+
+/// Pure rust version of the rule, the ground truth
+fn oracle_step(cells: &[u32], width: u32, height: u32, rule: &Rule) -> Vec<u32> {
+    let (w, h) = (width as i32, height as i32);
+    (0..cells.len() as i32)
+        .map(|index| {
+            let (x, y) = (index % w, index / w);
+            let color = cells[index as usize];
+            let enemy = (color + 1) % rule.colors;
+
+            let enemies = rule
+                .neighborhood
+                .iter()
+                .filter_map(|&(dx, dy)| {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx >= 0 && nx < w && ny >= 0 && ny < h {
+                        return Some(cells[(ny * w + nx) as usize]);
+                    }
+                    match rule.boundary {
+                        Boundary::Clip => None,
+                        Boundary::Fixed(color) => Some(color),
+                        Boundary::Wrap => {
+                            Some(cells[(ny.rem_euclid(h) * w + nx.rem_euclid(w)) as usize])
+                        }
+                        Boundary::Reflect => {
+                            let (nx, ny) = (reflect(nx, w), reflect(ny, h));
+                            Some(cells[(ny * w + nx) as usize])
+                        }
+                    }
+                })
+                .filter(|&color| color == enemy)
+                .count() as u32;
+
+            if rule.threshold.contains(&enemies) {
+                enemy
+            } else {
+                color
+            }
+        })
+        .collect()
+}
+
+/// Mirrors `c` on the edges of [0, len) until it's inside, edges not repeated
+fn reflect(mut c: i32, len: i32) -> i32 {
+    if len == 1 {
+        return 0;
+    }
+    while c < 0 || c >= len {
+        c = if c < 0 { -c } else { 2 * (len - 1) - c };
+    }
+    c
+}
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+/// Shared by all tests, `None` when no adapter is available
+fn gpu() -> Option<&'static Gpu> {
+    static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+    GPU.get_or_init(|| pollster::block_on(Gpu::new())).as_ref()
+}
+
+impl Gpu {
+    async fn new() -> Option<Self> {
+        let instance = wgpu::Instance::default();
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter?;
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return None;
+        }
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    required_features: wgpu::Features::empty(),
+                    required_limits: wgpu::Limits::downlevel_defaults(),
+                },
+                None,
+            )
+            .await
+            .ok()?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                "../src/shaders/compute.wgsl"
+            ))),
+        });
+        let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: true }),
+            ],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(
+                &device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &[&layout],
+                    push_constant_ranges: &[],
+                }),
+            ),
+            module: &module,
+            entry_point: "main",
+        });
+
+        Some(Self {
+            device,
+            queue,
+            layout,
+            pipeline,
+        })
+    }
+
+    /// Runs `generations` generations of the compute shader, returning the grid after each one
+    ///
+    /// The workgroups are dispatched in rows of at most `groups_per_row`, as the app does for big grids
+    fn run(
+        &self,
+        cells: &[u32],
+        width: u32,
+        height: u32,
+        rule: &Rule,
+        generations: usize,
+        groups_per_row: u32,
+    ) -> Vec<Vec<u32>> {
+        let total = width * height;
+        // same layout as the 'SimParams' struct of the shader
+        let params = [
+            width,
+            height,
+            total,
+            rule.colors,
+            *rule.threshold.start(),
+            *rule.threshold.end(),
+            rule.neighborhood.len() as u32,
+            match rule.boundary {
+                Boundary::Clip => 0,
+                Boundary::Wrap => 1,
+                Boundary::Reflect => 2,
+                Boundary::Fixed(_) => 3,
+            },
+            match rule.boundary {
+                Boundary::Fixed(color) => color,
+                _ => 0,
+            },
+        ];
+        let params_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let neighborhood = rule
+            .neighborhood
+            .iter()
+            .flat_map(|&(dx, dy)| [dx, dy])
+            .collect::<Vec<i32>>();
+        let neighborhood_buffer =
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&neighborhood),
+                    usage: wgpu::BufferUsages::STORAGE,
+                });
+        let cells_buffers = [0, 1].map(|_| {
+            self.device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(cells),
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                })
+        });
+        let bind_groups = [0, 1].map(|i| {
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: params_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: cells_buffers[i].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: cells_buffers[(i + 1) % 2].as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: neighborhood_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: cells_buffers[0].size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let groups = total.div_ceil(CELLS_PER_GROUP);
+        let row = groups.min(groups_per_row);
+        (0..generations)
+            .map(|generation| {
+                let mut encoder = self
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+                {
+                    let mut cpass = encoder.begin_compute_pass(&Default::default());
+                    cpass.set_pipeline(&self.pipeline);
+                    cpass.set_bind_group(0, &bind_groups[generation % 2], &[]);
+                    cpass.dispatch_workgroups(row, groups.div_ceil(row), 1);
+                }
+                encoder.copy_buffer_to_buffer(
+                    &cells_buffers[(generation + 1) % 2],
+                    0,
+                    &staging_buffer,
+                    0,
+                    staging_buffer.size(),
+                );
+                self.queue.submit(Some(encoder.finish()));
+
+                let slice = staging_buffer.slice(..);
+                slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+                self.device.poll(wgpu::Maintain::Wait);
+                let out = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+                staging_buffer.unmap();
+                out
+            })
+            .collect()
+    }
+}
+
+/// The random grid seeded by `assert_parity`
+fn seed_grid(width: u32, height: u32, colors: u32) -> Vec<u32> {
+    let mut rng = WyRand::new_seed((width as u64) << 32 | (height as u64) << 8 | colors as u64);
+    (0..width * height)
+        .map(|_| rng.generate_range(0..colors))
+        .collect()
+}
+
+/// Whether any cell of the grid seeded by `assert_parity` changes within `generations`, according to the oracle
+fn evolves(width: u32, height: u32, rule: &Rule, generations: usize) -> bool {
+    let seed = seed_grid(width, height, rule.colors);
+    let mut cells = seed.clone();
+    for _ in 0..generations {
+        cells = oracle_step(&cells, width, height, rule);
+    }
+    cells != seed
+}
+
+/// Seeds a random grid, then checks every generation of the gpu against the oracle
+fn assert_parity(width: u32, height: u32, rule: &Rule, generations: usize) {
+    assert_parity_in_rows(width, height, rule, generations, u32::MAX);
+}
+
+/// Same as `assert_parity`, the workgroups being dispatched in rows of at most `groups_per_row`
+fn assert_parity_in_rows(
+    width: u32,
+    height: u32,
+    rule: &Rule,
+    generations: usize,
+    groups_per_row: u32,
+) {
+    let Some(gpu) = gpu() else {
+        eprintln!("no adapter able to run compute shaders, skipping");
+        return;
+    };
+
+    let colors = rule.colors;
+    let mut expected = seed_grid(width, height, colors);
+
+    let gpu_generations = gpu.run(&expected, width, height, rule, generations, groups_per_row);
+    for (generation, actual) in gpu_generations.iter().enumerate() {
+        expected = oracle_step(&expected, width, height, rule);
+        assert!(
+            *actual == expected,
+            "{width}x{height} grid with {colors} colors, threshold {:?} and boundary {:?} diverged at generation {}",
+            rule.threshold,
+            rule.boundary,
+            generation + 1
+        );
+    }
+}
+
+#[test]
+fn square_grids() {
+    for size in [1, 7, 32, 100] {
+        assert_parity(size, size, &Rule::classic(3), 20);
+    }
+}
+
+#[test]
+fn non_square_grids() {
+    for (width, height) in [(1, 40), (40, 1), (17, 5), (64, 23), (23, 64), (150, 37)] {
+        assert_parity(width, height, &Rule::classic(3), 20);
+    }
+}
+
+#[test]
+fn dispatch_rows() {
+    // 24 workgroups: one per row, rows with a partial last one, and a single row
+    for groups_per_row in [1, 5, 24] {
+        assert_parity_in_rows(40, 30, &Rule::classic(3), 10, groups_per_row);
+    }
+}
+
+#[test]
+fn color_counts() {
+    // with many colors, a cell rarely has more than one enemy around
+    for colors in [2, 3, 4, 5, 6, 9, 10, 16, 64, 256, 257, 1000, 4096] {
+        let rule = Rule {
+            threshold: 1..=u32::MAX,
+            ..Rule::classic(colors)
+        };
+        assert!(
+            evolves(48, 31, &rule, 15),
+            "{colors} colors don't step, the parity would prove nothing"
+        );
+        assert_parity(48, 31, &rule, 15);
+    }
+}
+
+#[test]
+fn thresholds() {
+    for (colors, threshold) in [
+        (3, 1..=u32::MAX),
+        (4, 2..=u32::MAX),
+        (9, 3..=u32::MAX),
+        (16, 1..=1),
+        (5, 2..=3),
+        (3, 4..=8),
+    ] {
+        assert_parity(
+            40,
+            27,
+            &Rule {
+                threshold,
+                ..Rule::classic(colors)
+            },
+            15,
+        );
+    }
+}
+
+#[test]
+fn neighborhoods() {
+    let neighborhoods = [
+        // von neumann, radius 1 and 2
+        square(1, |dx, dy| dx.abs() + dy.abs() <= 1),
+        square(2, |dx, dy| dx.abs() + dy.abs() <= 2),
+        // moore, radius 2 and 3
+        square(2, |_, _| true),
+        square(3, |_, _| true),
+        // circular, radius 3
+        square(3, |dx, dy| dx * dx + dy * dy <= 9),
+        // asymmetric custom mask
+        vec![(1, 0), (0, 2), (-1, -1), (-3, 1)],
+    ];
+    for neighborhood in neighborhoods {
+        for (colors, min) in [(3, 2), (4, 3), (6, 1)] {
+            assert_parity(
+                37,
+                29,
+                &Rule {
+                    threshold: min..=u32::MAX,
+                    neighborhood: neighborhood.clone(),
+                    ..Rule::classic(colors)
+                },
+                12,
+            );
+        }
+    }
+}
+
+#[test]
+fn boundaries() {
+    let boundaries = [
+        Boundary::Clip,
+        Boundary::Wrap,
+        Boundary::Reflect,
+        Boundary::Fixed(0),
+        Boundary::Fixed(2),
+    ];
+    for boundary in boundaries {
+        // the radius 3 neighborhood is bigger than the smallest grids, so wraps and reflections happen several times
+        for (r, min) in [(1, 3), (3, 5)] {
+            for (width, height) in [(1, 1), (2, 5), (31, 22)] {
+                assert_parity(
+                    width,
+                    height,
+                    &Rule {
+                        threshold: min..=u32::MAX,
+                        neighborhood: square(r, |_, _| true),
+                        boundary,
+                        ..Rule::classic(3)
+                    },
+                    10,
+                );
+            }
+        }
+    }
+}